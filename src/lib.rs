@@ -22,13 +22,16 @@ use crate::nixpacks::{
     app::App,
     builder::{
         docker::{docker_image_builder::DockerImageBuilder, DockerBuilderOptions},
+        oci::oci_image_builder::OciImageBuilder,
         ImageBuilder,
     },
     environment::Environment,
     logger::Logger,
     nix::pkg::Pkg,
     plan::{
+        explain::PlanExplanation,
         generator::{GeneratePlanOptions, NixpacksBuildPlanGenerator},
+        lock::Lockfile,
         BuildPlan, PlanGenerator,
     },
 };
@@ -85,6 +88,46 @@ pub fn generate_build_plan(
     Ok(plan)
 }
 
+pub fn explain_build_plan(
+    path: &str,
+    envs: Vec<&str>,
+    options: &GeneratePlanOptions,
+) -> Result<PlanExplanation> {
+    let app = App::new(path)?;
+    let environment = Environment::from_envs(envs)?;
+
+    let generator = NixpacksBuildPlanGenerator::new(get_providers(), options.clone());
+
+    generator.explain_plan(&app, &environment)
+}
+
+/// Write a `nixpacks.lock` to the app directory that pins the resolved plan.
+///
+/// The build image is pulled to resolve its digest when `pull` is set and it is not available locally.
+pub fn lock_build_plan(
+    path: &str,
+    envs: Vec<&str>,
+    options: &GeneratePlanOptions,
+    pull: bool,
+) -> Result<Lockfile> {
+    let app = App::new(path)?;
+    let environment = Environment::from_envs(envs)?;
+
+    let generator = NixpacksBuildPlanGenerator::new(
+        get_providers(),
+        GeneratePlanOptions {
+            update_lock: true,
+            ..options.clone()
+        },
+    );
+
+    let mut lockfile = generator.get_lockfile(&app, &environment)?;
+    lockfile.resolve_build_image_digest(pull);
+    lockfile.write(&app.source)?;
+
+    Ok(lockfile)
+}
+
 pub fn get_plan_providers(
     path: &str,
     envs: Vec<&str>,
@@ -108,10 +151,17 @@ pub fn create_docker_image(
     let environment = Environment::from_envs(envs)?;
 
     let mut generator = NixpacksBuildPlanGenerator::new(get_providers(), plan_options.clone());
-    let plan = generator.generate_plan(&app, &environment)?;
+    let mut plan = generator.generate_plan(&app, &environment)?;
+    if build_options.metadata_file.is_some() {
+        // The providers are not kept in the generated plan, but are part of the metadata of the image
+        plan.providers = Some(generator.get_plan_providers(&app, &environment)?);
+    }
 
     let logger = Logger::new();
-    let builder = DockerImageBuilder::new(logger, build_options.clone());
+    let builder: Box<dyn ImageBuilder> = match build_options.builder.as_deref() {
+        Some("oci") => Box::new(OciImageBuilder::new(logger, build_options.clone())),
+        _ => Box::new(DockerImageBuilder::new(logger, build_options.clone())),
+    };
     builder.create_image(app.source.to_str().unwrap(), &plan, &environment)?;
 
     Ok(())
@@ -1,13 +1,18 @@
 use anyhow::{bail, Result};
 use clap::{arg, Arg, Command};
+use colored::Colorize;
 use nixpacks::{
-    create_docker_image, generate_build_plan, get_plan_providers,
+    create_docker_image, explain_build_plan, generate_build_plan, get_plan_providers,
+    lock_build_plan,
     nixpacks::{
         builder::docker::DockerBuilderOptions,
         nix::pkg::Pkg,
         plan::{
+            diff::PlanDiff,
             generator::GeneratePlanOptions,
+            lock::LOCKFILE_NAME,
             phase::{Phase, StartPhase},
+            schema::plan_schema,
             BuildPlan,
         },
     },
@@ -16,6 +21,7 @@ use std::{
     collections::hash_map::DefaultHasher,
     env,
     hash::{Hash, Hasher},
+    path::Path,
     string::ToString,
 };
 
@@ -50,6 +56,18 @@ fn main() -> Result<()> {
                         .short('f')
                         .takes_value(true)
                         .help("json|toml. Specify the output format of the plan"),
+                )
+                .arg(
+                    Arg::new("explain")
+                        .long("explain")
+                        .help("Show where every value of the plan came from and what it replaced"),
+                )
+                .arg(
+                    Arg::new("diff")
+                        .long("diff")
+                        .takes_value(true)
+                        .value_name("PLAN")
+                        .help("Compare the plan with a saved plan file (json|toml) or the plan of another app directory"),
                 ),
         )
         .subcommand(
@@ -57,6 +75,20 @@ fn main() -> Result<()> {
                 .about("List all of the providers that will be used to build the app")
                 .arg(arg!([PATH] "App source")),
         )
+        .subcommand(
+            Command::new("lock")
+                .about("Write a nixpacks.lock that pins the resolved build plan of an app")
+                .arg(arg!([PATH] "App source"))
+                .arg(
+                    Arg::new("pull")
+                        .long("pull")
+                        .help("Pull the build image with Docker when it is needed to resolve its digest"),
+                ),
+        )
+        .subcommand(
+            Command::new("schema")
+                .about("Print the JSON Schema for the nixpacks.toml and nixpacks.json config files"),
+        )
         .subcommand(
             Command::new("build")
                 .about("Create a docker image for an app")
@@ -142,13 +174,78 @@ fn main() -> Result<()> {
                 .arg(
                     Arg::new("cache-from")
                         .long("cache-from")
-                        .help("Image to consider as cache sources")
-                        .takes_value(true),
+                        .help("Caches to import, as images or type=registry,ref=IMAGE / type=local,src=DIR")
+                        .takes_value(true)
+                        .multiple_occurrences(true),
+                )
+                .arg(
+                    Arg::new("cache-to")
+                        .long("cache-to")
+                        .help("Caches to export, as images or type=registry,ref=IMAGE / type=local,dest=DIR / type=inline")
+                        .takes_value(true)
+                        .multiple_occurrences(true),
                 )
                 .arg(
                     Arg::new("no-error-without-start")
                         .long("no-error-without-start")
                         .help("Do not error when no start command can be found"),
+                )
+                .arg(
+                    Arg::new("secret")
+                        .long("secret")
+                        .help("Secrets to mount for the phases that use them, taken from the environment variable of the same name")
+                        .takes_value(true)
+                        .multiple_values(true),
+                )
+                .arg(
+                    Arg::new("secret-file")
+                        .long("secret-file")
+                        .help("Secrets to mount for the phases that use them, read from a file (NAME=PATH)")
+                        .takes_value(true)
+                        .multiple_values(true),
+                )
+                .arg(
+                    Arg::new("single-stage")
+                        .long("single-stage")
+                        .help("Run all phases in a single stage instead of a stage per phase"),
+                )
+                .arg(
+                    Arg::new("builder")
+                        .long("builder")
+                        .help("Build with docker, podman or buildah (by default the first that is installed and running), or write an OCI image to --out with oci, which puts the app on --base-rootfs without running the phases")
+                        .takes_value(true)
+                        .possible_values(["docker", "podman", "buildah", "oci"]),
+                )
+                .arg(
+                    Arg::new("bake")
+                        .long("bake")
+                        .help("Also write a docker-bake.json for `docker buildx bake` to the --out directory")
+                        .requires("out"),
+                )
+                .arg(
+                    Arg::new("push")
+                        .long("push")
+                        .help("Push the name and all tags of the image after it is built")
+                        .requires("name"),
+                )
+                .arg(
+                    Arg::new("digest-file")
+                        .long("digest-file")
+                        .help("Write the digests of the pushed image to this file")
+                        .takes_value(true)
+                        .requires("push"),
+                )
+                .arg(
+                    Arg::new("metadata-file")
+                        .long("metadata-file")
+                        .help("Write the id, tags, digests and providers of the image and the build duration to this JSON file")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("base-rootfs")
+                        .long("base-rootfs")
+                        .help("Tarball of the root filesystem that the oci builder puts the app on top of")
+                        .takes_value(true),
                 ),
         )
         .arg(
@@ -182,6 +279,13 @@ fn main() -> Result<()> {
                 .takes_value(true)
                 .global(true),
         )
+        .arg(
+            Arg::new("user")
+                .long("user")
+                .help("Run the container as this user instead of root")
+                .takes_value(true)
+                .global(true),
+        )
         .arg(
             Arg::new("pkgs")
                 .long("pkgs")
@@ -223,11 +327,31 @@ fn main() -> Result<()> {
                 .takes_value(true)
                 .global(true),
         )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .help("Apply the `[env.<profile>]` overlay from the config file")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::new("update-lock")
+                .long("update-lock")
+                .help("Regenerate the nixpacks.lock of the app before using it")
+                .global(true),
+        )
+        .arg(
+            Arg::new("strict")
+                .long("strict")
+                .help("Error on unknown keys in the config file instead of warning")
+                .global(true),
+        )
         .get_matches();
 
     let install_cmd = matches.value_of("install_cmd").map(|s| vec![s.to_string()]);
     let build_cmd = matches.value_of("build_cmd").map(|s| vec![s.to_string()]);
     let start_cmd = matches.value_of("start_cmd").map(ToString::to_string);
+    let user = matches.value_of("user").map(ToString::to_string);
     let pkgs = match matches.values_of("pkgs") {
         Some(values) => values.map(Pkg::new).collect::<Vec<_>>(),
         None => Vec::new(),
@@ -264,8 +388,12 @@ fn main() -> Result<()> {
         build.cmds = Some(build_cmds);
         cli_plan.add_phase(build);
     }
-    if let Some(start_cmd) = start_cmd {
-        let start = StartPhase::new(start_cmd);
+    if start_cmd.is_some() || user.is_some() {
+        let start = StartPhase {
+            cmd: start_cmd,
+            user,
+            ..Default::default()
+        };
         cli_plan.set_start_phase(start);
     }
 
@@ -285,11 +413,46 @@ fn main() -> Result<()> {
     let options = GeneratePlanOptions {
         plan: Some(cli_plan),
         config_file,
+        strict: matches.is_present("strict"),
+        profile: matches.value_of("profile").map(ToString::to_string),
+        update_lock: false,
     };
 
     match &matches.subcommand() {
         Some(("plan", matches)) => {
             let path = matches.value_of("PATH").unwrap_or(".");
+
+            if matches.is_present("update-lock") {
+                lock_build_plan(path, envs.clone(), &options, false)?;
+            }
+
+            if matches.is_present("explain") {
+                let explanation = explain_build_plan(path, envs, &options)?;
+                match matches.value_of("format") {
+                    None => print!("{}", explanation.to_text()),
+                    Some("json") => println!("{}", serde_json::to_string_pretty(&explanation)?),
+                    Some(_) => bail!("Plan explanations can only be output as text or json"),
+                }
+                return Ok(());
+            }
+
+            if let Some(other) = matches.value_of("diff") {
+                let before = if Path::new(other).is_dir() {
+                    generate_build_plan(other, envs.clone(), &options)?
+                } else {
+                    BuildPlan::from_file(Path::new(other))?
+                };
+                let after = generate_build_plan(path, envs, &options)?;
+
+                let diff = PlanDiff::new(&before, &after);
+                match matches.value_of("format") {
+                    None => print!("{}", diff.to_text()),
+                    Some("json") => println!("{}", serde_json::to_string_pretty(&diff)?),
+                    Some(_) => bail!("Plan diffs can only be output as text or json"),
+                }
+                return Ok(());
+            }
+
             let format = PlanFormat::from_str(matches.value_of("format").unwrap_or("json"))?;
 
             let plan = generate_build_plan(path, envs, &options)?;
@@ -307,8 +470,28 @@ fn main() -> Result<()> {
             let providers = get_plan_providers(path, envs, &options)?;
             println!("{}", providers.join(", "));
         }
+        Some(("lock", matches)) => {
+            let path = matches.value_of("PATH").unwrap_or(".");
+
+            let lockfile = lock_build_plan(path, envs, &options, matches.is_present("pull"))?;
+            if lockfile.build_image_digest.is_none() {
+                println!(
+                    "{}",
+                    "Warning: Could not resolve the digest of the build image. Pull it with Docker or pass `--pull`"
+                        .bright_yellow()
+                );
+            }
+            println!("Wrote {LOCKFILE_NAME}");
+        }
+        Some(("schema", _)) => {
+            println!("{}", serde_json::to_string_pretty(&plan_schema())?);
+        }
         Some(("build", matches)) => {
             let path = matches.value_of("PATH").unwrap_or(".");
+
+            if matches.is_present("update-lock") {
+                lock_build_plan(path, envs.clone(), &options, false)?;
+            }
             let name = matches.value_of("name").map(ToString::to_string);
             let out_dir = matches.value_of("out").map(ToString::to_string);
             let current_dir = matches.is_present("current-dir");
@@ -317,11 +500,18 @@ fn main() -> Result<()> {
             let inline_cache = matches.is_present("inline-cache");
             let verbose = matches.is_present("verbose") || envs.contains(&"NIXPACKS_VERBOSE=1");
 
-            let cache_from = if !no_cache {
-                matches.value_of("cache-from").map(ToString::to_string)
-            } else {
-                None
+            let get_caches = |name: &str| {
+                if no_cache {
+                    Vec::new()
+                } else {
+                    matches
+                        .values_of(name)
+                        .map(|values| values.map(ToString::to_string).collect::<Vec<_>>())
+                        .unwrap_or_default()
+                }
             };
+            let cache_from = get_caches("cache-from");
+            let cache_to = get_caches("cache-to");
 
             let incremental_cache_image = matches
                 .value_of("incremental-cache-image")
@@ -349,6 +539,21 @@ fn main() -> Result<()> {
                 .unwrap_or_default();
 
             let no_error_without_start = matches.is_present("no-error-without-start");
+            let single_stage = matches.is_present("single-stage");
+            let secrets = matches
+                .values_of("secret")
+                .map(|values| values.map(ToString::to_string).collect::<Vec<_>>())
+                .unwrap_or_default();
+            let secret_files = matches
+                .values_of("secret-file")
+                .map(|values| values.map(ToString::to_string).collect::<Vec<_>>())
+                .unwrap_or_default();
+            let builder = matches.value_of("builder").map(ToString::to_string);
+            let base_rootfs = matches.value_of("base-rootfs").map(ToString::to_string);
+            let bake = matches.is_present("bake");
+            let push = matches.is_present("push");
+            let digest_file = matches.value_of("digest-file").map(ToString::to_string);
+            let metadata_file = matches.value_of("metadata-file").map(ToString::to_string);
 
             let build_options = &DockerBuilderOptions {
                 name,
@@ -363,9 +568,19 @@ fn main() -> Result<()> {
                 current_dir,
                 inline_cache,
                 cache_from,
+                cache_to,
                 no_error_without_start,
                 incremental_cache_image,
                 verbose,
+                single_stage,
+                secrets,
+                secret_files,
+                builder,
+                base_rootfs,
+                bake,
+                push,
+                digest_file,
+                metadata_file,
             };
 
             create_docker_image(path, envs, &options, build_options)?;
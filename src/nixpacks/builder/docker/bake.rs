@@ -0,0 +1,184 @@
+use super::{cache::CacheBackend, DockerBuilderOptions};
+use crate::nixpacks::plan::BuildPlan;
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+pub const BAKE_FILE_NAME: &str = "docker-bake.json";
+
+/// Name of the target when the image has no name
+const DEFAULT_TARGET: &str = "nixpacks";
+
+/// A `docker buildx bake` definition that builds the image, https://docs.docker.com/build/bake/reference/
+#[derive(Serialize, PartialEq, Eq, Clone, Debug)]
+pub struct BakeFile {
+    pub group: BTreeMap<String, BakeGroup>,
+    pub target: BTreeMap<String, BakeTarget>,
+}
+
+#[derive(Serialize, PartialEq, Eq, Clone, Debug)]
+pub struct BakeGroup {
+    pub targets: Vec<String>,
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(Serialize, PartialEq, Eq, Clone, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct BakeTarget {
+    pub context: String,
+    pub dockerfile: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub platforms: Vec<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub args: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub secret: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cache_from: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cache_to: Vec<String>,
+    pub no_cache: Option<bool>,
+}
+
+impl BakeFile {
+    /// The bake file for building `dockerfile` in the output directory, with the `secrets` sources of the build
+    pub fn new(
+        plan: &BuildPlan,
+        options: &DockerBuilderOptions,
+        dockerfile: &str,
+        secrets: Vec<String>,
+    ) -> Result<Self> {
+        let target = BakeTarget {
+            context: ".".to_string(),
+            dockerfile: dockerfile.to_string(),
+            tags: options
+                .name
+                .iter()
+                .chain(options.tags.iter())
+                .cloned()
+                .collect(),
+            labels: options.get_labels()?,
+            platforms: options.platform.clone(),
+            args: plan.variables.clone().unwrap_or_default(),
+            secret: secrets,
+            cache_from: options
+                .get_cache_from()?
+                .iter()
+                .map(CacheBackend::get_import_arg)
+                .collect::<Result<_>>()?,
+            cache_to: options
+                .get_cache_to()?
+                .iter()
+                .map(CacheBackend::get_export_arg)
+                .collect(),
+            no_cache: if options.no_cache { Some(true) } else { None },
+        };
+
+        let name = get_target_name(options.name.as_deref());
+        Ok(BakeFile {
+            group: BTreeMap::from([(
+                "default".to_string(),
+                BakeGroup {
+                    targets: vec![name.clone()],
+                },
+            )]),
+            target: BTreeMap::from([(name, target)]),
+        })
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// Bake targets can only have letters, numbers, `-` and `_` in their name, so `ghcr.io/org/app:v1` is `app`
+fn get_target_name(image_name: Option<&str>) -> String {
+    let name = image_name
+        .map(|name| name.rsplit('/').next().unwrap_or(name))
+        .map(|name| name.split(':').next().unwrap_or(name))
+        .map(|name| {
+            name.chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                        c
+                    } else {
+                        '-'
+                    }
+                })
+                .collect::<String>()
+        })
+        .unwrap_or_default();
+
+    if name.is_empty() {
+        DEFAULT_TARGET.to_string()
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bake_file() -> Result<()> {
+        let plan = BuildPlan::from_toml(
+            r#"
+            [variables]
+            NODE_ENV = "production"
+            "#,
+        )?;
+        let options = DockerBuilderOptions {
+            name: Some("ghcr.io/org/my.app:v1".to_string()),
+            tags: vec!["my-app:latest".to_string()],
+            labels: vec!["team=web".to_string()],
+            platform: vec!["linux/amd64".to_string()],
+            cache_from: vec!["ghcr.io/org/my.app:cache".to_string()],
+            inline_cache: true,
+            ..Default::default()
+        };
+
+        let bake = BakeFile::new(
+            &plan,
+            &options,
+            ".nixpacks/Dockerfile",
+            vec!["id=NPM_TOKEN,env=NPM_TOKEN".to_string()],
+        )?;
+        assert_eq!(bake.group["default"].targets, vec!["my-app"]);
+
+        let json: serde_json::Value = serde_json::from_str(&bake.to_json()?)?;
+        let target = &json["target"]["my-app"];
+        assert_eq!(
+            target["tags"],
+            serde_json::json!(["ghcr.io/org/my.app:v1", "my-app:latest"])
+        );
+        assert_eq!(target["labels"]["team"], "web");
+        assert_eq!(target["args"]["NODE_ENV"], "production");
+        assert_eq!(
+            target["cache-from"],
+            serde_json::json!(["type=registry,ref=ghcr.io/org/my.app:cache"])
+        );
+        assert_eq!(target["cache-to"], serde_json::json!(["type=inline"]));
+        assert_eq!(
+            target["secret"],
+            serde_json::json!(["id=NPM_TOKEN,env=NPM_TOKEN"])
+        );
+        assert!(target.get("no-cache").is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bake_target_name() {
+        assert_eq!(get_target_name(None), "nixpacks");
+        assert_eq!(get_target_name(Some("app")), "app");
+        assert_eq!(
+            get_target_name(Some("localhost:5000/app.web:v1")),
+            "app-web"
+        );
+    }
+}
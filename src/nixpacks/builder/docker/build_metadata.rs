@@ -0,0 +1,34 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt::Write, fs};
+
+/// What was built, for tools that run after Nixpacks
+#[derive(Serialize, PartialEq, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildMetadata {
+    pub image_id: String,
+    pub name: String,
+    pub tags: Vec<String>,
+    /// The digest that the name and each tag were pushed with
+    pub digests: BTreeMap<String, String>,
+    pub providers: Vec<String>,
+    pub builder: String,
+    /// How long building the image took, in seconds
+    pub build_duration: f64,
+}
+
+impl BuildMetadata {
+    pub fn write(&self, path: &str) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+            .context(format!("Writing metadata file {path}"))
+    }
+}
+
+/// The contents of the `--digest-file`, a line of `name@digest` for each pushed name and tag
+pub fn get_digest_file_contents(digests: &[(String, String)]) -> String {
+    let mut contents = String::new();
+    for (reference, digest) in digests {
+        writeln!(contents, "{reference}@{digest}").unwrap();
+    }
+    contents
+}
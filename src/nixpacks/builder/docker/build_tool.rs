@@ -0,0 +1,251 @@
+use anyhow::{bail, Context, Result};
+use std::{
+    fs,
+    io::{BufRead, BufReader},
+    process::{Command, Stdio},
+};
+use tempdir::TempDir;
+
+/// The tools that can build the generated Dockerfile into an image
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BuildTool {
+    Docker,
+    Podman,
+    Buildah,
+}
+
+impl BuildTool {
+    /// All the tools, in the order they are looked for when none is chosen
+    pub const ALL: [BuildTool; 3] = [BuildTool::Docker, BuildTool::Podman, BuildTool::Buildah];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        BuildTool::ALL.into_iter().find(|tool| tool.name() == name)
+    }
+
+    /// The tool that was chosen with `--builder`, or else the first one that is installed and can be used
+    pub fn find(name: Option<&str>) -> Result<Self> {
+        let name = match name {
+            Some(name) => name,
+            None => {
+                return BuildTool::ALL
+                    .into_iter()
+                    .find(|tool| tool.is_installed() && tool.is_reachable())
+                    .context("Please install Docker, Podman or Buildah to build the app, or start the Docker daemon if it is installed https://docs.docker.com/engine/install/")
+            }
+        };
+
+        let tool =
+            BuildTool::from_name(name).with_context(|| format!("Unknown builder `{name}`"))?;
+        if !tool.is_installed() {
+            bail!(
+                "Please install {} to build the app {}",
+                tool.title(),
+                tool.install_url()
+            );
+        }
+        if !tool.is_reachable() {
+            bail!(
+                "{} is installed but `{} info` failed, check that it is running and that you can use it",
+                tool.title(),
+                tool.name()
+            );
+        }
+
+        Ok(tool)
+    }
+
+    /// Name of the binary of the tool
+    pub fn name(self) -> &'static str {
+        match self {
+            BuildTool::Docker => "docker",
+            BuildTool::Podman => "podman",
+            BuildTool::Buildah => "buildah",
+        }
+    }
+
+    pub fn title(self) -> &'static str {
+        match self {
+            BuildTool::Docker => "Docker",
+            BuildTool::Podman => "Podman",
+            BuildTool::Buildah => "Buildah",
+        }
+    }
+
+    fn install_url(self) -> &'static str {
+        match self {
+            BuildTool::Docker => "https://docs.docker.com/engine/install/",
+            BuildTool::Podman => "https://podman.io/getting-started/installation",
+            BuildTool::Buildah => "https://github.com/containers/buildah/blob/main/install.md",
+        }
+    }
+
+    pub fn is_installed(self) -> bool {
+        self.runs_successfully("--version")
+    }
+
+    /// Whether the tool can build, e.g. the Docker daemon is running and the user may connect to it
+    pub fn is_reachable(self) -> bool {
+        self.runs_successfully("info")
+    }
+
+    fn runs_successfully(self, arg: &str) -> bool {
+        Command::new(self.name())
+            .arg(arg)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map_or(false, |status| status.success())
+    }
+
+    /// Whether the tool builds with BuildKit, which has inline cache metadata and progress output
+    pub fn uses_buildkit(self) -> bool {
+        self == BuildTool::Docker
+    }
+
+    /// The driver of the current buildx builder, e.g. `docker` or `docker-container`
+    pub fn get_buildx_driver(self) -> Result<String> {
+        let output = Command::new(self.name())
+            .args(["buildx", "inspect"])
+            .stderr(Stdio::inherit())
+            .output()
+            .context("Inspecting the buildx builder")?;
+        if !output.status.success() {
+            bail!("Failed to inspect the buildx builder, check that buildx is installed");
+        }
+
+        parse_buildx_driver(&String::from_utf8_lossy(&output.stdout))
+            .context("Failed to find the driver of the buildx builder")
+    }
+
+    /// The command to run images that were built by the tool. Buildah stores them for Podman.
+    pub fn get_run_command(self, name: &str) -> String {
+        match self {
+            BuildTool::Docker => format!("docker run -it {name}"),
+            BuildTool::Podman | BuildTool::Buildah => format!("podman run -it {name}"),
+        }
+    }
+
+    /// The id of an image that was built by the tool
+    pub fn get_image_id(self, name: &str) -> Result<String> {
+        let mut inspect_cmd = Command::new(self.name());
+        match self {
+            BuildTool::Docker | BuildTool::Podman => {
+                inspect_cmd.args(["image", "inspect", "--format", "{{.Id}}", name])
+            }
+            BuildTool::Buildah => inspect_cmd.args([
+                "inspect",
+                "--type",
+                "image",
+                "--format",
+                "{{.FromImageID}}",
+                name,
+            ]),
+        };
+
+        let output = inspect_cmd
+            .stderr(Stdio::inherit())
+            .output()
+            .context("Inspecting image")?;
+        if !output.status.success() {
+            bail!("Failed to find the id of image {name}");
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// Push the image, or the manifest list of a build for several platforms, and return the digest it was pushed with
+    pub fn push(self, reference: &str, is_manifest_list: bool) -> Result<String> {
+        let mut push_cmd = Command::new(self.name());
+
+        let digest = if self == BuildTool::Docker {
+            // Docker reports the digest in its output
+            let mut child = push_cmd
+                .arg("push")
+                .arg(reference)
+                .stdout(Stdio::piped())
+                .spawn()
+                .context("Pushing image")?;
+
+            let mut digest = None;
+            if let Some(stdout) = child.stdout.take() {
+                for line in BufReader::new(stdout).lines() {
+                    let line = line?;
+                    println!("{line}");
+                    digest = digest.or_else(|| parse_push_digest(&line));
+                }
+            }
+            if !child.wait()?.success() {
+                bail!("Failed to push {reference}");
+            }
+            digest
+        } else {
+            // Podman and Buildah write the digest to a file
+            let tmp = TempDir::new("nixpacks-push").context("Creating a temp directory")?;
+            let digest_file = tmp.path().join("digest");
+
+            if is_manifest_list {
+                push_cmd.args(["manifest", "push", "--all"]);
+            } else {
+                push_cmd.arg("push");
+            }
+            push_cmd
+                .arg("--digestfile")
+                .arg(&digest_file)
+                .arg(reference);
+            if is_manifest_list {
+                push_cmd.arg(format!("docker://{reference}"));
+            }
+
+            if !push_cmd.status().context("Pushing image")?.success() {
+                bail!("Failed to push {reference}");
+            }
+            fs::read_to_string(&digest_file)
+                .ok()
+                .map(|digest| digest.trim().to_string())
+        };
+
+        digest
+            .with_context(|| format!("Failed to find the digest that {reference} was pushed with"))
+    }
+}
+
+/// The digest in the last line of `docker push`, e.g. `latest: digest: sha256:abc size: 1234`
+fn parse_push_digest(line: &str) -> Option<String> {
+    let (_, rest) = line.split_once("digest: ")?;
+    rest.split_whitespace()
+        .next()
+        .filter(|digest| digest.starts_with("sha256:"))
+        .map(ToString::to_string)
+}
+
+/// The driver in the output of `docker buildx inspect`, e.g. `Driver:        docker-container`
+fn parse_buildx_driver(output: &str) -> Option<String> {
+    output
+        .lines()
+        .find_map(|line| line.strip_prefix("Driver:"))
+        .map(|driver| driver.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_push_digest() {
+        assert_eq!(
+            parse_push_digest("v1: digest: sha256:0123abcd size: 1784"),
+            Some("sha256:0123abcd".to_string())
+        );
+        assert_eq!(parse_push_digest("5f70bf18a086: Pushed"), None);
+    }
+
+    #[test]
+    fn test_parse_buildx_driver() {
+        let output = "Name:          builder\nDriver:        docker-container\nLast Activity: 2022-10-17 01:00:00 +0000 UTC\n\nNodes:\nName:      builder0\n";
+        assert_eq!(
+            parse_buildx_driver(output),
+            Some("docker-container".to_string())
+        );
+        assert_eq!(parse_buildx_driver("Name: default\n"), None);
+    }
+}
@@ -1,3 +1,111 @@
+use anyhow::{bail, Context, Result};
+use std::{collections::BTreeMap, str::FromStr};
+
+/// Where the build cache is imported from or exported to, https://docs.docker.com/build/cache/backends/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CacheBackend {
+    /// An image in a registry
+    Registry {
+        reference: String,
+        mode: Option<String>,
+    },
+    /// A directory on the host
+    Local { path: String, mode: Option<String> },
+    /// Cache metadata that is written into the built image
+    Inline,
+}
+
+impl FromStr for CacheBackend {
+    type Err = anyhow::Error;
+
+    /// Parse `type=registry,ref=<image>`, `type=local,dest=<dir>` (or `src=<dir>`), `type=inline`
+    /// or an image reference on its own, which is a registry cache
+    fn from_str(value: &str) -> Result<Self> {
+        if !value.contains('=') {
+            return Ok(CacheBackend::Registry {
+                reference: value.to_string(),
+                mode: None,
+            });
+        }
+
+        let mut attrs = BTreeMap::new();
+        for attr in value.split(',') {
+            let (key, attr_value) = attr.split_once('=').with_context(|| {
+                format!("Cache `{value}` should be in the form type=TYPE,KEY=VALUE,...")
+            })?;
+            attrs.insert(key.trim(), attr_value.trim().to_string());
+        }
+
+        let mode = attrs.get("mode").cloned();
+        if let Some(mode) = &mode {
+            if mode != "min" && mode != "max" {
+                bail!("Unknown cache mode `{mode}`, use min or max");
+            }
+        }
+
+        match attrs.get("type").map(String::as_str) {
+            Some("registry") => match attrs.get("ref") {
+                Some(reference) => Ok(CacheBackend::Registry {
+                    reference: reference.clone(),
+                    mode,
+                }),
+                None => bail!("Registry cache `{value}` needs a ref"),
+            },
+            Some("local") => match attrs.get("dest").or_else(|| attrs.get("src")) {
+                Some(path) => Ok(CacheBackend::Local {
+                    path: path.clone(),
+                    mode,
+                }),
+                None => bail!("Local cache `{value}` needs a dest or src directory"),
+            },
+            Some("inline") => Ok(CacheBackend::Inline),
+            Some(cache_type) => {
+                bail!("Unknown cache type `{cache_type}`, use registry, local or inline")
+            }
+            None => bail!("Cache `{value}` needs a type"),
+        }
+    }
+}
+
+impl CacheBackend {
+    /// The `--cache-from` value for BuildKit
+    pub fn get_import_arg(&self) -> Result<String> {
+        match self {
+            CacheBackend::Registry { reference, .. } => {
+                Ok(format!("type=registry,ref={reference}"))
+            }
+            CacheBackend::Local { path, .. } => Ok(format!("type=local,src={path}")),
+            CacheBackend::Inline => {
+                bail!("An inline cache is imported from the image it was written to, pass the image to --cache-from instead")
+            }
+        }
+    }
+
+    /// The `--cache-to` value for BuildKit
+    pub fn get_export_arg(&self) -> String {
+        let (arg, mode) = match self {
+            CacheBackend::Registry { reference, mode } => {
+                (format!("type=registry,ref={reference}"), mode)
+            }
+            CacheBackend::Local { path, mode } => (format!("type=local,dest={path}"), mode),
+            CacheBackend::Inline => return "type=inline".to_string(),
+        };
+
+        match mode {
+            Some(mode) => format!("{arg},mode={mode}"),
+            None => arg,
+        }
+    }
+}
+
+/// Parse the `--cache-from` or `--cache-to` values
+pub fn parse_cache_backends(values: &[String]) -> Result<Vec<CacheBackend>> {
+    values
+        .iter()
+        .map(|value| CacheBackend::from_str(value).context(format!("Invalid cache `{value}`")))
+        .collect()
+}
+
 pub fn sanitize_cache_key(cache_key: &str) -> String {
     cache_key
         .chars()
@@ -23,4 +131,38 @@ mod tests {
         );
         assert_eq!(sanitize_cache_key("/.m2"), "/m2".to_string());
     }
+
+    #[test]
+    fn test_parse_cache_backends() -> Result<()> {
+        let backends = parse_cache_backends(&[
+            "ghcr.io/org/app:cache".to_string(),
+            "type=registry,ref=ghcr.io/org/app:cache,mode=max".to_string(),
+            "type=local,dest=/tmp/cache".to_string(),
+            "type=inline".to_string(),
+        ])?;
+
+        assert_eq!(
+            backends
+                .iter()
+                .map(CacheBackend::get_export_arg)
+                .collect::<Vec<_>>(),
+            vec![
+                "type=registry,ref=ghcr.io/org/app:cache",
+                "type=registry,ref=ghcr.io/org/app:cache,mode=max",
+                "type=local,dest=/tmp/cache",
+                "type=inline"
+            ]
+        );
+        assert_eq!(
+            backends[2].get_import_arg()?,
+            "type=local,src=/tmp/cache".to_string()
+        );
+        assert!(backends[3].get_import_arg().is_err());
+
+        assert!(parse_cache_backends(&["type=gha".to_string()]).is_err());
+        assert!(parse_cache_backends(&["type=local".to_string()]).is_err());
+        assert!(parse_cache_backends(&["type=local,dest=x,mode=all".to_string()]).is_err());
+
+        Ok(())
+    }
 }
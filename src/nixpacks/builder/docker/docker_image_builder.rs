@@ -1,6 +1,10 @@
 use super::{dockerfile_generation::DockerfileGenerator, DockerBuilderOptions, ImageBuilder};
 use crate::nixpacks::{
     builder::docker::{
+        bake::{BakeFile, BAKE_FILE_NAME},
+        build_metadata::{self, BuildMetadata},
+        build_tool::BuildTool,
+        cache::CacheBackend,
         dockerfile_generation::OutputDir,
         file_server::FileServer,
         incremental_cache::{IncrementalCache, IncrementalCacheDirs},
@@ -11,9 +15,14 @@ use crate::nixpacks::{
     plan::BuildPlan,
 };
 use anyhow::{bail, Context, Ok, Result};
+use path_slash::PathBufExt;
 use std::{
+    collections::BTreeMap,
+    env,
     fs::{self, remove_dir_all, File},
+    path::Path,
     process::Command,
+    time::Instant,
 };
 use tempdir::TempDir;
 use uuid::Uuid;
@@ -70,7 +79,8 @@ impl ImageBuilder for DockerImageBuilder {
             println!("{}", plan.get_build_string()?);
 
             let start = plan.start_phase.clone().unwrap_or_default();
-            if start.cmd.is_none() && !self.options.no_error_without_start {
+            if start.cmd.is_none() && !start.has_processes() && !self.options.no_error_without_start
+            {
                 bail!("No start command could be found")
             }
         } else {
@@ -78,25 +88,72 @@ impl ImageBuilder for DockerImageBuilder {
             std::process::exit(1);
         }
 
-        self.write_app(app_src, &output).context("Writing app")?;
+        self.write_app(app_src, plan, &output)
+            .context("Writing app")?;
         self.write_dockerfile(dockerfile, &output)
             .context("Writing Dockerfile")?;
         plan.write_supporting_files(&self.options, env, &output)
             .context("Writing supporting files")?;
+        if self.options.bake {
+            self.write_bake_file(plan, &output)
+                .context("Writing docker-bake.json")?;
+        }
 
         // Only build if the --out flag was not specified
         if self.options.out_dir.is_none() {
-            let mut docker_build_cmd = self.get_docker_build_cmd(plan, name.as_str(), &output)?;
+            let tool = BuildTool::find(self.options.builder.as_deref())?;
+            if self.options.incremental_cache_image.is_some() && tool != BuildTool::Docker {
+                bail!("Incremental cache images are only supported when building with Docker");
+            }
+            if self.options.push && self.options.name.is_none() {
+                bail!("Images can only be pushed when they have a name, pass one with --name");
+            }
+
+            if self.uses_buildx(tool)? && tool.get_buildx_driver()? == "docker" {
+                bail!("Registry and local caches need a buildx builder that does not use the docker driver, create one with `docker buildx create --use --driver docker-container`");
+            }
+
+            let mut docker_build_cmd =
+                self.get_docker_build_cmd(tool, plan, name.as_str(), &output)?;
 
             // Execute docker build
+            let build_start = Instant::now();
             let build_result = docker_build_cmd.spawn()?.wait().context("Building image")?;
             if !build_result.success() {
-                bail!("Docker build failed")
+                bail!("{} build failed", tool.title())
             }
+            let build_duration = build_start.elapsed();
 
             self.logger.log_section("Successfully Built!");
             println!("\nRun:");
-            println!("  docker run -it {}", name);
+            println!("  {}", tool.get_run_command(&name));
+
+            let digests = if self.options.push {
+                self.push_image(tool, &name)?
+            } else {
+                Vec::new()
+            };
+
+            if let Some(digest_file) = &self.options.digest_file {
+                fs::write(
+                    digest_file,
+                    build_metadata::get_digest_file_contents(&digests),
+                )
+                .context(format!("Writing digest file {digest_file}"))?;
+            }
+
+            if let Some(metadata_file) = &self.options.metadata_file {
+                BuildMetadata {
+                    image_id: tool.get_image_id(&name)?,
+                    name: name.clone(),
+                    tags: self.options.tags.clone(),
+                    digests: digests.into_iter().collect(),
+                    providers: plan.providers.clone().unwrap_or_default(),
+                    builder: tool.name().to_string(),
+                    build_duration: build_duration.as_secs_f64(),
+                }
+                .write(metadata_file)?;
+            }
 
             if self.options.incremental_cache_image.is_some() {
                 incremental_cache.create_image(
@@ -122,30 +179,64 @@ impl DockerImageBuilder {
         DockerImageBuilder { logger, options }
     }
 
+    /// `docker build` can only import caches from images and export an inline cache
+    fn uses_buildx(&self, tool: BuildTool) -> Result<bool> {
+        Ok(tool.uses_buildkit()
+            && (self
+                .options
+                .get_cache_to()?
+                .iter()
+                .any(|cache| *cache != CacheBackend::Inline)
+                || self
+                    .options
+                    .get_cache_from()?
+                    .iter()
+                    .any(|cache| matches!(cache, CacheBackend::Local { .. }))))
+    }
+
     fn get_docker_build_cmd(
         &self,
+        tool: BuildTool,
         plan: &BuildPlan,
         name: &str,
         output: &OutputDir,
     ) -> Result<Command> {
-        let mut docker_build_cmd = Command::new("docker");
+        let mut docker_build_cmd = Command::new(tool.name());
 
-        if docker_build_cmd.output().is_err() {
-            bail!("Please install Docker to build the app https://docs.docker.com/engine/install/")
+        if tool.uses_buildkit() {
+            // Enable BuildKit for all builds
+            docker_build_cmd.env("DOCKER_BUILDKIT", "1");
         }
 
-        // Enable BuildKit for all builds
-        docker_build_cmd.env("DOCKER_BUILDKIT", "1");
+        let cache_from = self.options.get_cache_from()?;
+        let cache_to = self.options.get_cache_to()?;
+
+        let use_buildx = self.uses_buildx(tool)?;
+        if use_buildx {
+            // Load the image into Docker, which a builder that is not the default one does not do
+            docker_build_cmd.arg("buildx").arg("build").arg("--load");
+        } else {
+            docker_build_cmd.arg("build");
+        }
 
         docker_build_cmd
-            .arg("build")
-            .arg(&output.root)
             .arg("-f")
-            .arg(&output.get_absolute_path("Dockerfile"))
-            .arg("-t")
-            .arg(name);
+            .arg(&output.get_absolute_path("Dockerfile"));
+
+        // Podman and Buildah put the images for several platforms in a manifest list
+        if !tool.uses_buildkit() && self.options.platform.len() > 1 {
+            if !self.options.tags.is_empty() {
+                bail!(
+                    "Tags can not be added when building for more than one platform with {}",
+                    tool.title()
+                );
+            }
+            docker_build_cmd.arg("--manifest").arg(name);
+        } else {
+            docker_build_cmd.arg("-t").arg(name);
+        }
 
-        if self.options.verbose {
+        if self.options.verbose && tool.uses_buildkit() {
             docker_build_cmd.arg("--progress=plain");
         }
 
@@ -155,16 +246,56 @@ impl DockerImageBuilder {
 
         if self.options.no_cache {
             docker_build_cmd.arg("--no-cache");
+        } else if tool == BuildTool::Buildah {
+            // Buildah does not keep the layers of a build unless asked to, so nothing would be cached
+            docker_build_cmd.arg("--layers");
         }
 
-        if let Some(value) = &self.options.cache_from {
-            docker_build_cmd.arg("--cache-from").arg(value);
+        if tool.uses_buildkit() {
+            for cache in &cache_from {
+                match cache {
+                    CacheBackend::Registry { reference, .. } if !use_buildx => {
+                        docker_build_cmd.arg("--cache-from").arg(reference)
+                    }
+                    _ => docker_build_cmd
+                        .arg("--cache-from")
+                        .arg(cache.get_import_arg()?),
+                };
+            }
+            for cache in &cache_to {
+                if *cache == CacheBackend::Inline && !use_buildx {
+                    docker_build_cmd
+                        .arg("--build-arg")
+                        .arg("BUILDKIT_INLINE_CACHE=1");
+                } else {
+                    docker_build_cmd
+                        .arg("--cache-to")
+                        .arg(cache.get_export_arg());
+                }
+            }
+        } else {
+            // Podman and Buildah take the image of a registry cache on its own
+            for (flag, caches) in [("--cache-from", &cache_from), ("--cache-to", &cache_to)] {
+                for cache in caches {
+                    match cache {
+                        CacheBackend::Registry { reference, .. } => {
+                            docker_build_cmd.arg(flag).arg(reference)
+                        }
+                        CacheBackend::Local { .. } => {
+                            bail!("{} can only use registry caches", tool.title())
+                        }
+                        CacheBackend::Inline => {
+                            bail!("Inline cache is only supported when building with Docker")
+                        }
+                    };
+                }
+            }
         }
 
-        if self.options.inline_cache {
-            docker_build_cmd
-                .arg("--build-arg")
-                .arg("BUILDKIT_INLINE_CACHE=1");
+        // Only BuildKit looks for the ignore file next to the Dockerfile
+        let ignore_file = output.get_absolute_path("Dockerfile.dockerignore");
+        if !tool.uses_buildkit() && ignore_file.is_file() {
+            docker_build_cmd.arg("--ignorefile").arg(ignore_file);
         }
 
         // Add build environment variables
@@ -174,6 +305,10 @@ impl DockerImageBuilder {
                 .arg(format!("{}={}", name, value));
         }
 
+        for secret in self.get_secret_sources(plan, true)? {
+            docker_build_cmd.arg("--secret").arg(secret);
+        }
+
         // Add user defined tags and labels to the image
         for t in self.options.tags.clone() {
             docker_build_cmd.arg("-t").arg(t);
@@ -181,21 +316,130 @@ impl DockerImageBuilder {
         for l in self.options.labels.clone() {
             docker_build_cmd.arg("--label").arg(l);
         }
-        for l in self.options.platform.clone() {
-            docker_build_cmd.arg("--platform").arg(l);
+        if tool.uses_buildkit() {
+            for l in self.options.platform.clone() {
+                docker_build_cmd.arg("--platform").arg(l);
+            }
+        } else if !self.options.platform.is_empty() {
+            docker_build_cmd
+                .arg("--platform")
+                .arg(self.options.platform.join(","));
         }
 
+        docker_build_cmd.arg(&output.root);
+
         Ok(docker_build_cmd)
     }
 
-    fn write_app(&self, app_src: &str, output: &OutputDir) -> Result<()> {
+    /// The `--secret` values for all the secrets that are passed in or used by the phases of the plan.
+    /// Secrets that are not passed in explicitly are taken from the environment variable of the same name,
+    /// which has to be set when `require_env` is true.
+    fn get_secret_sources(&self, plan: &BuildPlan, require_env: bool) -> Result<Vec<String>> {
+        let mut sources = BTreeMap::new();
+
+        for secret in &self.options.secret_files {
+            let (name, path) = secret.split_once('=').with_context(|| {
+                format!("Secret file `{secret}` should be in the form NAME=PATH")
+            })?;
+            if !Path::new(path).is_file() {
+                bail!("Secret file `{path}` for `{name}` does not exist");
+            }
+            sources.insert(name.to_string(), format!("id={name},src={path}"));
+        }
+
+        let used_secrets = plan
+            .phases
+            .clone()
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|(name, phase)| {
+                phase
+                    .secrets
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(move |secret| (secret, name.clone()))
+            })
+            .collect::<Vec<_>>();
+
+        let env_secrets = self
+            .options
+            .secrets
+            .iter()
+            .map(|secret| (secret.clone(), None))
+            .chain(
+                used_secrets
+                    .into_iter()
+                    .map(|(secret, phase)| (secret, Some(phase))),
+            );
+
+        for (name, phase) in env_secrets {
+            if sources.contains_key(&name) {
+                continue;
+            }
+            if require_env && env::var_os(&name).is_none() {
+                match phase {
+                    Some(phase) => bail!("Secret `{name}` is used by phase `{phase}` but was not provided. Pass it with --secret or --secret-file"),
+                    None => bail!("Secret `{name}` is not set in the environment"),
+                }
+            }
+            sources.insert(name.clone(), format!("id={name},env={name}"));
+        }
+
+        Ok(sources.into_values().collect())
+    }
+
+    /// Copy the app into the build context, or tell Docker what to leave out when the context is not a copy
+    fn write_app(&self, app_src: &str, plan: &BuildPlan, output: &OutputDir) -> Result<()> {
+        let excludes =
+            files::get_ignore_patterns(app_src, &plan.exclude.clone().unwrap_or_default())?;
+
         if output.is_temp {
-            files::recursive_copy_dir(app_src, &output.root)
+            files::recursive_copy_dir(app_src, &output.root, &excludes)
+        } else if !excludes.is_empty() {
+            // BuildKit reads the ignore file next to the Dockerfile instead of the `.dockerignore`
+            fs::write(
+                output.get_absolute_path("Dockerfile.dockerignore"),
+                excludes.join("\n") + "\n",
+            )
+            .context("Writing Dockerfile.dockerignore")
         } else {
             Ok(())
         }
     }
 
+    /// Push the name and every tag of the image, returning the digest that each was pushed with
+    fn push_image(&self, tool: BuildTool, name: &str) -> Result<Vec<(String, String)>> {
+        let is_manifest_list = !tool.uses_buildkit() && self.options.platform.len() > 1;
+
+        let mut digests = Vec::new();
+        for reference in std::iter::once(name).chain(self.options.tags.iter().map(String::as_str)) {
+            self.logger.log_step(&format!("Pushing {reference}"));
+            let digest = tool.push(reference, is_manifest_list)?;
+            digests.push((reference.to_string(), digest));
+        }
+
+        self.logger.log_section("Successfully Pushed!");
+        for (reference, digest) in &digests {
+            println!("  {reference}@{digest}");
+        }
+
+        Ok(digests)
+    }
+
+    /// The secrets are only needed when the bake file is built, so they don't have to be set yet
+    fn write_bake_file(&self, plan: &BuildPlan, output: &OutputDir) -> Result<()> {
+        let dockerfile = output
+            .get_relative_path("Dockerfile")
+            .to_slash()
+            .context("Failed to convert Dockerfile path to slash path.")?
+            .to_string();
+        let secrets = self.get_secret_sources(plan, false)?;
+        let bake_file = BakeFile::new(plan, &self.options, &dockerfile, secrets)?;
+
+        fs::write(output.root.join(BAKE_FILE_NAME), bake_file.to_json()?)?;
+        Ok(())
+    }
+
     fn write_dockerfile(&self, dockerfile: String, output: &OutputDir) -> Result<()> {
         let dockerfile_path = output.get_absolute_path("Dockerfile");
         File::create(dockerfile_path.clone()).context("Creating Dockerfile file")?;
@@ -204,3 +448,101 @@ impl DockerImageBuilder {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_args(tool: BuildTool, options: DockerBuilderOptions) -> Result<Vec<String>> {
+        let builder = DockerImageBuilder::new(Logger::new(), options);
+        let output = OutputDir::new("/tmp/app".into(), false)?;
+        let cmd = builder.get_docker_build_cmd(tool, &BuildPlan::default(), "app", &output)?;
+
+        Ok(cmd
+            .get_args()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect())
+    }
+
+    #[test]
+    fn test_build_cmd_platforms() -> Result<()> {
+        let options = DockerBuilderOptions {
+            platform: vec!["linux/amd64".to_string(), "linux/arm64".to_string()],
+            ..Default::default()
+        };
+
+        let docker_args = get_args(BuildTool::Docker, options.clone())?.join(" ");
+        assert!(docker_args.contains("-t app"));
+        assert!(docker_args.contains("--platform linux/amd64 --platform linux/arm64"));
+
+        let podman_args = get_args(BuildTool::Podman, options.clone())?.join(" ");
+        assert!(podman_args.contains("--manifest app"));
+        assert!(podman_args.contains("--platform linux/amd64,linux/arm64"));
+        assert!(!podman_args.contains("--layers"));
+
+        let buildah_args = get_args(BuildTool::Buildah, options.clone())?;
+        assert!(buildah_args.contains(&"--layers".to_string()));
+        assert_eq!(buildah_args.last(), Some(&"/tmp/app".to_string()));
+
+        let tagged = DockerBuilderOptions {
+            tags: vec!["app:v1".to_string()],
+            ..options
+        };
+        assert!(get_args(BuildTool::Podman, tagged).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_cmd_caches() -> Result<()> {
+        let options = DockerBuilderOptions {
+            cache_from: vec!["ghcr.io/org/app:cache".to_string()],
+            inline_cache: true,
+            ..Default::default()
+        };
+        let args = get_args(BuildTool::Docker, options.clone())?;
+        assert_eq!(args[0], "build");
+        let args = args.join(" ");
+        assert!(args.contains("--cache-from ghcr.io/org/app:cache"));
+        assert!(args.contains("--build-arg BUILDKIT_INLINE_CACHE=1"));
+
+        let options = DockerBuilderOptions {
+            cache_from: vec![
+                "ghcr.io/org/app:cache".to_string(),
+                "type=local,src=/tmp/cache".to_string(),
+            ],
+            cache_to: vec!["type=local,dest=/tmp/cache,mode=max".to_string()],
+            ..options
+        };
+        let args = get_args(BuildTool::Docker, options.clone())?;
+        assert_eq!(args[..3], ["buildx", "build", "--load"]);
+        let args = args.join(" ");
+        assert!(args.contains("--cache-from type=registry,ref=ghcr.io/org/app:cache"));
+        assert!(args.contains("--cache-from type=local,src=/tmp/cache"));
+        assert!(args.contains("--cache-to type=local,dest=/tmp/cache,mode=max"));
+        assert!(args.contains("--cache-to type=inline"));
+
+        let registry_only = DockerBuilderOptions {
+            cache_from: vec!["ghcr.io/org/app:cache".to_string()],
+            cache_to: vec!["type=registry,ref=ghcr.io/org/app:cache".to_string()],
+            ..Default::default()
+        };
+        let args = get_args(BuildTool::Podman, registry_only)?.join(" ");
+        assert!(args.contains("--cache-from ghcr.io/org/app:cache"));
+        assert!(args.contains("--cache-to ghcr.io/org/app:cache"));
+        assert!(get_args(BuildTool::Podman, options).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_inline_cache_needs_buildkit() {
+        let options = DockerBuilderOptions {
+            inline_cache: true,
+            ..Default::default()
+        };
+
+        assert!(get_args(BuildTool::Docker, options.clone()).is_ok());
+        assert!(get_args(BuildTool::Buildah, options).is_err());
+    }
+}
@@ -3,18 +3,21 @@ use super::{
 };
 use crate::nixpacks::{
     app,
-    environment::Environment,
+    environment::{Environment, VariableScope},
     images::DEFAULT_BASE_IMAGE,
     nix::{create_nix_expressions_for_phases, nix_file_names_for_phases},
     plan::{
-        phase::{Phase, StartPhase},
+        phase::{Phase, StartPhase, INIT_PATH, PROCESS_LAUNCHER, SUPPORTED_INITS},
         BuildPlan,
     },
 };
-use anyhow::{Context, Ok, Result};
+use anyhow::{bail, Context, Ok, Result};
+use colored::Colorize;
 use indoc::formatdoc;
 use path_slash::PathBufExt;
+use regex::Regex;
 use std::{
+    collections::BTreeSet,
     fs::{self, File},
     io::Write,
     path::{Path, PathBuf},
@@ -23,6 +26,14 @@ use std::{
 const NIXPACKS_OUTPUT_DIR: &str = ".nixpacks";
 pub const APP_DIR: &str = "/app/";
 
+const BASE_STAGE: &str = "base";
+const APP_STAGE: &str = "app";
+
+const PROCESS_LAUNCHER_FILE: &str = "nixpacks-start";
+
+/// Names that `useradd` accepts by default
+const USER_NAME_REGEX: &str = "^[a-z_][a-z0-9_-]*$";
+
 #[derive(Debug, Clone)]
 pub struct OutputDir {
     pub root: PathBuf,
@@ -103,7 +114,9 @@ impl DockerfileGenerator for BuildPlan {
         output: &OutputDir,
         file_server_config: Option<FileServerConfig>,
     ) -> Result<String> {
-        let plan = self;
+        let mut plan = self.clone();
+        plan.unescape_variables();
+        let plan = &plan;
 
         let nix_file_names = nix_file_names_for_phases(&plan.phases.clone().unwrap_or_default());
 
@@ -132,26 +145,17 @@ impl DockerfileGenerator for BuildPlan {
             )
         };
 
-        let variables = plan.variables.clone().unwrap_or_default();
-        let args_string = if variables.is_empty() {
-            String::new()
-        } else {
-            format!(
-                "ARG {}\nENV {}",
-                // Pull the variables in from docker `--build-arg`
-                variables
-                    .iter()
-                    .map(|var| var.0.to_string())
-                    .collect::<Vec<_>>()
-                    .join(" "),
-                // Make the variables available at runtime
-                variables
-                    .iter()
-                    .map(|var| format!("{}=${}", var.0, var.0))
-                    .collect::<Vec<_>>()
-                    .join(" ")
-            )
-        };
+        // Build only variables are not saved in the image and runtime only variables are set in the final stage
+        let build_variables = plan.get_variables_in_scope(VariableScope::Build);
+        let build_args = utils::get_arg_command(&build_variables);
+        let args_string = [
+            utils::get_env_command(&plan.get_variables_in_scope(VariableScope::Both)),
+            build_args.clone(),
+        ]
+        .into_iter()
+        .filter(|stmt| !stmt.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
 
         let static_assets = plan.static_assets.clone().unwrap_or_default();
         let assets_copy_cmd = if static_assets.is_empty() {
@@ -165,9 +169,10 @@ impl DockerfileGenerator for BuildPlan {
         };
 
         let phases = plan.get_sorted_phases()?;
+        let single_stage = options.single_stage || env.is_config_variable_truthy("SINGLE_STAGE");
 
         let dockerfile_phases = phases
-            .into_iter()
+            .iter()
             .map(|phase| {
                 let phase_dockerfile = phase
                     .generate_dockerfile(options, env, output, file_server_config.clone())
@@ -176,16 +181,56 @@ impl DockerfileGenerator for BuildPlan {
                         phase.get_name()
                     ))?;
 
-                Ok(phase_dockerfile)
+                if single_stage {
+                    return Ok(phase_dockerfile);
+                }
+
+                let dependencies =
+                    get_outermost_phases(&phases, &phase.depends_on.clone().unwrap_or_default());
+                let mut stage_start =
+                    get_stage_start(&phases, &dependencies, &get_stage_name(&phase.get_name()));
+
+                // Build arguments are scoped to the stage they are declared in
+                if !build_args.is_empty() {
+                    stage_start = format!("{stage_start}\n{build_args}");
+                }
+
+                Ok(format!("{stage_start}\n{phase_dockerfile}"))
             })
             .collect::<Result<Vec<_>>>()?;
         let dockerfile_phases_str = dockerfile_phases.join("\n");
 
-        let start_phase_str = plan
-            .start_phase
-            .clone()
-            .unwrap_or_default()
-            .generate_dockerfile(options, env, output, file_server_config)?;
+        // Each phase is built in its own stage so that BuildKit can run independent phases in parallel.
+        // The outputs of the phases that nothing depends on are then brought together in the app stage.
+        let (base_stage, app_stage) = if single_stage {
+            (String::new(), String::new())
+        } else {
+            let final_phases = get_outermost_phases(
+                &phases,
+                &phases.iter().map(Phase::get_name).collect::<Vec<_>>(),
+            );
+            (
+                format!(" AS {BASE_STAGE}"),
+                get_stage_start(&phases, &final_phases, APP_STAGE),
+            )
+        };
+
+        // A separate run image does not have any of the variables of the build image
+        let start_phase = plan.start_phase.clone().unwrap_or_default();
+        let runtime_variables = if start_phase.run_image.is_some() {
+            [
+                plan.get_variables_in_scope(VariableScope::Both),
+                plan.get_variables_in_scope(VariableScope::Runtime),
+            ]
+            .concat()
+        } else {
+            plan.get_variables_in_scope(VariableScope::Runtime)
+        };
+        let start_phase_str = start_phase.generate_start_dockerfile(
+            output,
+            if single_stage { "0" } else { APP_STAGE },
+            &utils::get_env_command(&runtime_variables),
+        )?;
 
         let base_image = plan
             .build_image
@@ -193,7 +238,7 @@ impl DockerfileGenerator for BuildPlan {
             .unwrap_or_else(|| DEFAULT_BASE_IMAGE.to_string());
 
         let dockerfile = formatdoc! {"
-            FROM {base_image}
+            FROM {base_image}{base_stage}
 
             ENTRYPOINT [\"/bin/bash\", \"-l\", \"-c\"]
             WORKDIR {APP_DIR}
@@ -205,9 +250,12 @@ impl DockerfileGenerator for BuildPlan {
 
             {dockerfile_phases_str}
 
+            {app_stage}
             {start_phase_str}
         ", 
         base_image=base_image,
+        base_stage=base_stage,
+        app_stage=app_stage,
         APP_DIR=APP_DIR,
         nix_install_cmds=nix_install_cmds,
         apt_pkgs_str=apt_pkgs_str,
@@ -244,6 +292,12 @@ impl DockerfileGenerator for BuildPlan {
                 .context(format!("Writing files for phase {}", phase.get_name()))?;
         }
 
+        if let Some(start_phase) = &self.start_phase {
+            start_phase
+                .write_supporting_files(options, env, output)
+                .context("Writing files for start phase")?;
+        }
+
         Ok(())
     }
 }
@@ -281,40 +335,272 @@ impl BuildPlan {
     }
 }
 
-impl DockerfileGenerator for StartPhase {
-    fn generate_dockerfile(
+/// Name of the build stage that a phase is run in
+fn get_stage_name(phase_name: &str) -> String {
+    let name = phase_name
+        .to_lowercase()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect::<String>();
+
+    format!("phase-{name}")
+}
+
+/// Start a new build stage from the stages of `phases`.
+/// The first phase is built upon and the app directory of the others is copied in, along with
+/// what they installed outside of it and the paths they added.
+fn get_stage_start(sorted_phases: &[Phase], phases: &[String], stage: &str) -> String {
+    let from = phases
+        .first()
+        .map_or_else(|| BASE_STAGE.to_string(), |name| get_stage_name(name));
+    let mut stmts = vec![format!("FROM {from} AS {stage}")];
+
+    // The phases whose outputs are already in the stage
+    let mut included = phases
+        .first()
+        .map(|name| get_phases_up_to(sorted_phases, name))
+        .unwrap_or_default();
+
+    for name in phases.iter().skip(1) {
+        let from = get_stage_name(name);
+        stmts.push(format!("COPY --from={from} {APP_DIR} {APP_DIR}"));
+
+        // Paths of later phases come first, as they would when the phases are run one by one
+        let paths = sorted_phases
+            .iter()
+            .rev()
+            .filter(|phase| {
+                let phase_name = phase.get_name();
+                get_phases_up_to(sorted_phases, name).contains(&phase_name)
+                    && included.insert(phase_name)
+            })
+            .flat_map(|phase| phase.paths.clone().unwrap_or_default())
+            .collect::<Vec<_>>();
+
+        let output_dirs = paths
+            .iter()
+            .filter_map(|path| get_output_dir(path))
+            .collect::<BTreeSet<_>>();
+        stmts.extend(
+            output_dirs
+                .iter()
+                .map(|dir| format!("COPY --from={from} {dir} {dir}")),
+        );
+
+        let path_cmd = utils::get_path_command(&paths);
+        if !path_cmd.is_empty() {
+            stmts.push(path_cmd);
+        }
+    }
+
+    stmts.join("\n")
+}
+
+/// Directories that are too broad to be copied between stages as a whole
+const SYSTEM_DIRS: &[&str] = &["/usr", "/usr/local", "/opt", "/root", "/home"];
+
+/// The directory outside of `/app` that a phase installed into, found from a path it added to
+/// `PATH`. The `bin` directory of e.g. a virtual environment needs the rest of the environment,
+/// so the directory above it is used.
+fn get_output_dir(path: &str) -> Option<String> {
+    let path = path.trim_end_matches('/');
+    if !path.starts_with('/') || path.contains('$') || format!("{path}/").starts_with(APP_DIR) {
+        return None;
+    }
+
+    match path.strip_suffix("/bin") {
+        Some(dir) if !dir.is_empty() && !SYSTEM_DIRS.contains(&dir) => Some(dir.to_string()),
+        _ => Some(path.to_string()),
+    }
+}
+
+/// The phase and all the phases it depends on, directly or through other phases
+fn get_phases_up_to(sorted_phases: &[Phase], name: &str) -> BTreeSet<String> {
+    let mut phases = BTreeSet::from([name.to_string()]);
+    let mut queue = vec![name.to_string()];
+    while let Some(current) = queue.pop() {
+        let phase = sorted_phases.iter().find(|p| p.get_name() == current);
+        for dependency in phase.and_then(|p| p.depends_on.clone()).unwrap_or_default() {
+            if phases.insert(dependency.clone()) {
+                queue.push(dependency);
+            }
+        }
+    }
+    phases
+}
+
+/// The phases from `names` that none of the other phases from `names` depend on, directly or
+/// through other phases. The phase that is sorted last comes first.
+///
+/// Copying in a phase that another phase already depends on would overwrite the files that the
+/// later phase changed with their older versions.
+fn get_outermost_phases(sorted_phases: &[Phase], names: &[String]) -> Vec<String> {
+    let inner_phases = names
+        .iter()
+        .flat_map(|name| {
+            let mut dependencies = get_phases_up_to(sorted_phases, name);
+            dependencies.remove(name);
+            dependencies
+        })
+        .collect::<BTreeSet<_>>();
+
+    sorted_phases
+        .iter()
+        .rev()
+        .map(Phase::get_name)
+        .filter(|name| names.contains(name) && !inner_phases.contains(name))
+        .collect()
+}
+
+impl StartPhase {
+    /// The start of the image, where the app files are copied from the `build_stage` if a separate run image is used
+    /// `runtime_env` sets the variables that are only available in the final image.
+    fn generate_start_dockerfile(
         &self,
-        _options: &DockerBuilderOptions,
-        _env: &Environment,
-        _output: &OutputDir,
-        _file_server_config: Option<FileServerConfig>,
+        output: &OutputDir,
+        build_stage: &str,
+        runtime_env: &str,
     ) -> Result<String> {
-        let start_cmd = match &self.cmd {
-            Some(cmd) => utils::get_exec_command(cmd),
-            None => String::new(),
+        // Named processes are started through a launcher so `docker run <image> <process>` works
+        let (entrypoint_cmd, start_cmd) = if self.has_processes() {
+            let launcher_path = output.get_relative_path(PROCESS_LAUNCHER_FILE);
+            let launcher_file = launcher_path
+                .to_slash()
+                .context("Failed to convert process launcher path to slash path.")?;
+            let default_process = self.get_default_process().unwrap_or_default();
+
+            (
+                formatdoc! {"
+                    COPY {launcher_file} {PROCESS_LAUNCHER}
+                    ENTRYPOINT {entrypoint}",
+                    launcher_file=launcher_file,
+                    PROCESS_LAUNCHER=PROCESS_LAUNCHER,
+                    entrypoint=utils::get_exec_form(&self.get_launcher_entrypoint(PROCESS_LAUNCHER)),
+                },
+                utils::get_exec_command(&default_process),
+            )
+        } else {
+            let start_cmd = match &self.cmd {
+                Some(_) if self.is_exec() => {
+                    format!("CMD {}", utils::get_exec_form(&self.get_command()?))
+                }
+                Some(cmd) => utils::get_exec_command(cmd),
+                None => String::new(),
+            };
+
+            let entrypoint_cmd = if self.has_custom_entrypoint() {
+                format!(
+                    "ENTRYPOINT {}",
+                    utils::get_exec_form(&self.get_entrypoint())
+                )
+            } else {
+                String::new()
+            };
+            (entrypoint_cmd, start_cmd)
+        };
+
+        // The init is copied out of the build, which is the stage before the run image
+        let (build_init_cmd, init_cmd) = match &self.init {
+            Some(init) if self.entrypoint.is_none() => {
+                if !SUPPORTED_INITS.contains(&init.as_str()) {
+                    bail!(
+                        "Unknown init `{init}`, use one of {}",
+                        SUPPORTED_INITS.join(", ")
+                    );
+                }
+                let copy_init_cmd = utils::get_copy_init_command(init);
+                if self.run_image.is_some() {
+                    (
+                        format!("{copy_init_cmd}\n"),
+                        format!("COPY --from={build_stage} {INIT_PATH} {INIT_PATH}"),
+                    )
+                } else {
+                    (String::new(), copy_init_cmd)
+                }
+            }
+            _ => (String::new(), String::new()),
+        };
+
+        let nix_path_cmd = if self.user.is_some() || self.is_exec() {
+            utils::get_nix_profile_path_command()
+        } else {
+            String::new()
+        };
+
+        let expose_cmd = utils::get_expose_command(&self.ports);
+        // Run images may not have the `curl` that checks a `path`
+        let healthcheck = match &self.healthcheck {
+            Some(healthcheck) if self.run_image.is_some() && healthcheck.cmd.is_none() => {
+                println!(
+                    "{}",
+                    "Warning: The healthcheck is left out of the image, as the run image may not have curl. Set `start.healthcheck.cmd` to check it another way"
+                        .bright_yellow()
+                );
+                None
+            }
+            healthcheck => healthcheck.clone(),
+        };
+        let healthcheck_cmd = utils::get_healthcheck_command(&healthcheck);
+
+        let (create_user_cmd, user_cmd) = match &self.user {
+            Some(user) => {
+                if !Regex::new(USER_NAME_REGEX)?.is_match(user) {
+                    bail!("Invalid user name `{user}`. Use lowercase letters, digits, `_` and `-`");
+                }
+                (
+                    utils::get_create_user_command(
+                        user,
+                        &self.writable_directories.clone().unwrap_or_default(),
+                    ),
+                    format!("USER {user}"),
+                )
+            }
+            None => (String::new(), String::new()),
         };
 
         let dockerfile: String = match &self.run_image {
             Some(run_image) => {
                 let copy_cmd = utils::get_copy_from_command(
-                    "0",
+                    build_stage,
                     &self.only_include_files.clone().unwrap_or_default(),
                     APP_DIR,
                 );
 
                 // RUN true to prevent a Docker bug https://github.com/moby/moby/issues/37965#issuecomment-426853382
                 formatdoc! {"
-                  # start
+                  {build_init_cmd}# start
                   FROM {run_image}
                   WORKDIR {APP_DIR}
-                  COPY --from=0 /etc/ssl/certs /etc/ssl/certs
+                  COPY --from={build_stage} /etc/ssl/certs /etc/ssl/certs
                   RUN true
                   {copy_cmd}
+                  {runtime_env}
+                  {create_user_cmd}
+                  {init_cmd}
+                  {expose_cmd}
+                  {healthcheck_cmd}
+                  {entrypoint_cmd}
+                  {user_cmd}
                   {start_cmd}
                 ",
+                build_init_cmd=build_init_cmd,
                 run_image=run_image,
+                build_stage=build_stage,
                 APP_DIR=APP_DIR,
                 copy_cmd=copy_cmd,
+                create_user_cmd=create_user_cmd,
+                init_cmd=init_cmd,
+                runtime_env=runtime_env,
+                expose_cmd=expose_cmd,
+                healthcheck_cmd=healthcheck_cmd,
+                entrypoint_cmd=entrypoint_cmd,
+                user_cmd=user_cmd,
                 start_cmd=start_cmd,}
             }
             None => {
@@ -322,7 +608,23 @@ impl DockerfileGenerator for StartPhase {
                   # start
                   COPY . /app
                   {}
+                  {}
+                  {}
+                  {}
+                  {}
+                  {}
+                  {}
+                  {}
+                  {}
                 ",
+                runtime_env,
+                nix_path_cmd,
+                create_user_cmd,
+                init_cmd,
+                expose_cmd,
+                healthcheck_cmd,
+                entrypoint_cmd,
+                user_cmd,
                 start_cmd}
             }
         };
@@ -331,6 +633,34 @@ impl DockerfileGenerator for StartPhase {
     }
 }
 
+impl DockerfileGenerator for StartPhase {
+    fn generate_dockerfile(
+        &self,
+        _options: &DockerBuilderOptions,
+        _env: &Environment,
+        output: &OutputDir,
+        _file_server_config: Option<FileServerConfig>,
+    ) -> Result<String> {
+        self.generate_start_dockerfile(output, "0", "")
+    }
+
+    fn write_supporting_files(
+        &self,
+        _options: &DockerBuilderOptions,
+        _env: &Environment,
+        output: &OutputDir,
+    ) -> Result<()> {
+        if self.has_processes() {
+            let launcher =
+                utils::get_process_launcher(&self.processes.clone().unwrap_or_default())?;
+            fs::write(output.get_absolute_path(PROCESS_LAUNCHER_FILE), launcher)
+                .context("Writing process launcher")?;
+        }
+
+        Ok(())
+    }
+}
+
 impl DockerfileGenerator for Phase {
     fn generate_dockerfile(
         &self,
@@ -352,18 +682,7 @@ impl DockerfileGenerator for Phase {
         };
 
         // Ensure paths are available in the environment
-        let (build_path, run_path) = if let Some(paths) = &phase.paths {
-            let joined_paths = paths.join(":");
-            (
-                format!("ENV PATH {}:$PATH", joined_paths),
-                format!(
-                    "RUN printf '\\nPATH={}:$PATH' >> /root/.profile",
-                    joined_paths
-                ),
-            )
-        } else {
-            (String::new(), String::new())
-        };
+        let path_cmd = utils::get_path_command(&phase.paths.clone().unwrap_or_default());
 
         // Copy over app files
         let phase_files = match (phase.get_name().as_str(), &phase.only_include_files) {
@@ -373,6 +692,18 @@ impl DockerfileGenerator for Phase {
         let phase_copy_cmd = utils::get_copy_command(&phase_files, APP_DIR);
 
         let cache_mount = utils::get_cache_mount(&cache_key, &phase.cache_directories);
+        let secret_mount = utils::get_secret_mount(&phase.secrets);
+        let env_prefix = utils::get_phase_env_prefix(&phase.variables, &phase.secrets);
+        let run_cmd = |cmd: &String, mounts: &[&String]| {
+            let mut parts = mounts
+                .iter()
+                .filter(|mount| !mount.is_empty())
+                .map(|mount| mount.as_str())
+                .collect::<Vec<_>>();
+            let cmd = format!("{env_prefix}{cmd}");
+            parts.push(&cmd);
+            format!("RUN {}", parts.join(" "))
+        };
         let cmds_str = if options.incremental_cache_image.is_some() {
             let image = &options.incremental_cache_image.clone().unwrap();
             let cache_copy_in_command = if IncrementalCache::is_image_exists(image)? {
@@ -393,7 +724,7 @@ impl DockerfileGenerator for Phase {
             ]
             .concat()
             .iter()
-            .map(|s| format!("RUN {}", s))
+            .map(|s| run_cmd(s, &[&secret_mount]))
             .collect::<Vec<_>>()
             .join("\n");
 
@@ -404,12 +735,12 @@ impl DockerfileGenerator for Phase {
                 .clone()
                 .unwrap_or_default()
                 .iter()
-                .map(|s| format!("RUN {} {}", cache_mount, s))
+                .map(|s| run_cmd(s, &[&cache_mount, &secret_mount]))
                 .collect::<Vec<_>>()
                 .join("\n")
         };
 
-        let dockerfile_stmts = vec![build_path, run_path, phase_copy_cmd, cmds_str]
+        let dockerfile_stmts = vec![path_cmd, phase_copy_cmd, cmds_str]
             .into_iter()
             .filter(|stmt| !stmt.is_empty())
             .collect::<Vec<_>>()
@@ -439,6 +770,7 @@ impl DockerfileGenerator for Phase {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::nixpacks::{environment::EnvironmentVariables, plan::phase::Healthcheck};
 
     #[test]
     fn test_phase_generation() {
@@ -458,6 +790,25 @@ mod tests {
         assert!(dockerfile.contains("echo test"));
     }
 
+    #[test]
+    fn test_phase_generation_with_secrets() {
+        let mut phase = Phase::new("install");
+        phase.add_cmd("npm ci");
+        phase.add_variable("NPM_CONFIG_LOGLEVEL", "warn");
+        phase.add_secret("NPM_TOKEN");
+
+        let dockerfile = phase
+            .generate_dockerfile(
+                &DockerBuilderOptions::default(),
+                &Environment::default(),
+                &OutputDir::default(),
+                None,
+            )
+            .unwrap();
+
+        assert!(dockerfile.contains("RUN --mount=type=secret,id=NPM_TOKEN export NPM_CONFIG_LOGLEVEL='warn' NPM_TOKEN=\"$(cat /run/secrets/NPM_TOKEN)\" && npm ci"));
+    }
+
     #[test]
     fn test_plan_generation() {
         let mut plan = BuildPlan::default();
@@ -485,4 +836,305 @@ mod tests {
         assert!(dockerfile.contains("apt-get update"));
         assert!(dockerfile.contains("wget"));
     }
+
+    #[test]
+    fn test_plan_generation_with_stages() {
+        let mut plan = BuildPlan::default();
+        plan.add_phase(Phase::setup(None));
+
+        let mut frontend = Phase::new("frontend");
+        frontend.add_cmd("npm run build");
+        frontend.depends_on_phase("setup");
+        plan.add_phase(frontend);
+
+        let mut backend = Phase::new("backend");
+        backend.add_cmd("go build");
+        backend.depends_on_phase("setup");
+        plan.add_phase(backend);
+
+        let mut start = StartPhase::new("./server");
+        start.run_image = Some("debian:slim".to_string());
+        plan.set_start_phase(start);
+
+        let dockerfile = plan
+            .generate_dockerfile(
+                &DockerBuilderOptions::default(),
+                &Environment::default(),
+                &OutputDir::default(),
+                None,
+            )
+            .unwrap();
+
+        assert!(dockerfile.contains(" AS base\n"));
+        assert!(dockerfile.contains("FROM base AS phase-setup\n"));
+        assert!(dockerfile.contains("FROM phase-setup AS phase-frontend\n"));
+        assert!(dockerfile.contains("FROM phase-setup AS phase-backend\n"));
+        assert!(dockerfile
+            .contains("FROM phase-frontend AS app\nCOPY --from=phase-backend /app/ /app/\n"));
+        assert!(dockerfile.contains("COPY --from=app /app/ /app/"));
+
+        let single_stage = plan
+            .generate_dockerfile(
+                &DockerBuilderOptions {
+                    single_stage: true,
+                    ..Default::default()
+                },
+                &Environment::default(),
+                &OutputDir::default(),
+                None,
+            )
+            .unwrap();
+
+        assert!(!single_stage.contains("phase-"));
+        assert!(single_stage.contains("COPY --from=0 /app/ /app/"));
+    }
+
+    #[test]
+    fn test_plan_generation_keeps_outputs_outside_app() {
+        let mut plan = BuildPlan::default();
+        plan.add_phase(Phase::setup(None));
+
+        let mut build = Phase::new("build");
+        build.add_cmd("npm run build");
+        build.add_path("/app/node_modules/.bin".to_string());
+        build.depends_on_phase("setup");
+        plan.add_phase(build);
+
+        // Like the install phase of a second provider, which does not depend on the first one
+        let mut python_install = Phase::new("python:install");
+        python_install.add_cmd("python -m venv /opt/venv && pip install -r requirements.txt");
+        python_install.add_path("/opt/venv/bin".to_string());
+        python_install.depends_on_phase("setup");
+        plan.add_phase(python_install);
+
+        plan.set_start_phase(StartPhase::new("npm run start"));
+
+        let dockerfile = plan
+            .generate_dockerfile(
+                &DockerBuilderOptions::default(),
+                &Environment::default(),
+                &OutputDir::default(),
+                None,
+            )
+            .unwrap();
+
+        // The app stage is built upon one branch and gets the outputs and paths of the other
+        let app_stage = &dockerfile[dockerfile.find("FROM phase-python-install AS app").unwrap()..];
+        assert!(app_stage.contains(
+            "COPY --from=phase-build /app/ /app/\nENV PATH /app/node_modules/.bin:$PATH\n"
+        ));
+
+        let phases = plan.get_sorted_phases().unwrap();
+        let stage_start = get_stage_start(
+            &phases,
+            &["build".to_string(), "python:install".to_string()],
+            APP_STAGE,
+        );
+        assert_eq!(
+            stage_start,
+            "FROM phase-build AS app\n\
+             COPY --from=phase-python-install /app/ /app/\n\
+             COPY --from=phase-python-install /opt/venv /opt/venv\n\
+             ENV PATH /opt/venv/bin:$PATH\n\
+             RUN printf '\\nPATH=/opt/venv/bin:$PATH' >> /root/.profile"
+        );
+    }
+
+    #[test]
+    fn test_get_output_dir() {
+        assert_eq!(
+            get_output_dir("/opt/venv/bin"),
+            Some("/opt/venv".to_string())
+        );
+        assert_eq!(
+            get_output_dir("/root/.local/bin/"),
+            Some("/root/.local".to_string())
+        );
+        assert_eq!(
+            get_output_dir("/usr/local/bin"),
+            Some("/usr/local/bin".to_string())
+        );
+        assert_eq!(get_output_dir("/app/node_modules/.bin"), None);
+        assert_eq!(get_output_dir("$HOME/.cargo/bin"), None);
+    }
+
+    #[test]
+    fn test_plan_generation_with_variable_scopes() {
+        let mut plan = BuildPlan::default();
+        let mut build = Phase::new("build");
+        build.add_cmd("npm run build");
+        plan.add_phase(build);
+        plan.set_start_phase(StartPhase::new("npm run start"));
+
+        plan.add_variables(EnvironmentVariables::from([(
+            "NODE_ENV".to_string(),
+            "production".to_string(),
+        )]));
+        plan.add_scoped_variables(
+            EnvironmentVariables::from([("CI".to_string(), "true".to_string())]),
+            VariableScope::Build,
+        );
+        plan.add_scoped_variables(
+            EnvironmentVariables::from([("PORT".to_string(), "3000".to_string())]),
+            VariableScope::Runtime,
+        );
+
+        let dockerfile = plan
+            .generate_dockerfile(
+                &DockerBuilderOptions::default(),
+                &Environment::default(),
+                &OutputDir::default(),
+                None,
+            )
+            .unwrap();
+
+        assert!(dockerfile.contains("ARG NODE_ENV\nENV NODE_ENV=$NODE_ENV\nARG CI\n"));
+        assert!(dockerfile.contains("FROM base AS phase-build\nARG CI\n"));
+        assert!(!dockerfile.contains("ENV CI"));
+        assert!(dockerfile.contains("COPY . /app\nARG PORT\nENV PORT=$PORT\n"));
+        assert_eq!(dockerfile.matches("PORT").count(), 3);
+    }
+
+    #[test]
+    fn test_start_phase_with_processes() {
+        let mut start = StartPhase::new("npm run start");
+        start.add_process("web", "npm run start");
+        start.add_process("worker", "npm run worker");
+
+        let dockerfile = start
+            .generate_dockerfile(
+                &DockerBuilderOptions::default(),
+                &Environment::default(),
+                &OutputDir::default(),
+                None,
+            )
+            .unwrap();
+
+        assert!(dockerfile.contains("COPY .nixpacks/nixpacks-start /usr/local/bin/nixpacks-start"));
+        assert!(dockerfile
+            .contains("ENTRYPOINT [\"/bin/bash\", \"-l\", \"/usr/local/bin/nixpacks-start\"]"));
+        assert!(dockerfile.contains("CMD [\"web\"]"));
+    }
+
+    #[test]
+    fn test_start_phase_with_healthcheck() {
+        let mut start = StartPhase::new("npm run start");
+        start.healthcheck = Some(Healthcheck::http("/health", 3000));
+
+        let dockerfile = start
+            .generate_dockerfile(
+                &DockerBuilderOptions::default(),
+                &Environment::default(),
+                &OutputDir::default(),
+                None,
+            )
+            .unwrap();
+
+        let healthcheck = dockerfile.find("HEALTHCHECK CMD curl").unwrap();
+        assert!(healthcheck < dockerfile.find("CMD [\"npm run start\"]").unwrap());
+
+        // A run image may not have curl, but can still run a command
+        start.run_in_slim_image();
+        let generate = |start: &StartPhase| {
+            start
+                .generate_dockerfile(
+                    &DockerBuilderOptions::default(),
+                    &Environment::default(),
+                    &OutputDir::default(),
+                    None,
+                )
+                .unwrap()
+        };
+        assert!(!generate(&start).contains("HEALTHCHECK"));
+
+        start.healthcheck = Some(Healthcheck {
+            cmd: Some("/app/bin/check".to_string()),
+            ..Default::default()
+        });
+        assert!(generate(&start).contains("HEALTHCHECK CMD /app/bin/check"));
+    }
+
+    #[test]
+    fn test_start_phase_with_user() {
+        let mut start = StartPhase::new("npm run start");
+        start.run_in_slim_image();
+        start.user = Some("app".to_string());
+        start.add_writable_directory("tmp");
+
+        let dockerfile = start
+            .generate_dockerfile(
+                &DockerBuilderOptions::default(),
+                &Environment::default(),
+                &OutputDir::default(),
+                None,
+            )
+            .unwrap();
+
+        let copy = dockerfile.find("COPY --from=0").unwrap();
+        let create_user = dockerfile.find("useradd").unwrap();
+        let user = dockerfile.find("USER app\nCMD").unwrap();
+        assert!(copy < create_user && create_user < user);
+        assert!(dockerfile.contains("chown -R app /app /app/tmp"));
+
+        start.user = Some("app; rm -rf /".to_string());
+        assert!(start
+            .generate_dockerfile(
+                &DockerBuilderOptions::default(),
+                &Environment::default(),
+                &OutputDir::default(),
+                None,
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_start_phase_with_exec_and_init() {
+        let mut start = StartPhase::new("node index.js --name 'my app'");
+        start.run_in_slim_image();
+        start.exec = Some(true);
+        start.init = Some("dumb-init".to_string());
+
+        let dockerfile = start
+            .generate_dockerfile(
+                &DockerBuilderOptions::default(),
+                &Environment::default(),
+                &OutputDir::default(),
+                None,
+            )
+            .unwrap();
+
+        let copy_init = dockerfile
+            .find("RUN cp \"$(readlink -f \"$(command -v dumb-init)\")\"")
+            .unwrap();
+        assert!(copy_init < dockerfile.find("FROM debian").unwrap());
+        assert!(dockerfile
+            .contains("COPY --from=0 /usr/local/bin/nixpacks-init /usr/local/bin/nixpacks-init"));
+        assert!(dockerfile.contains("ENTRYPOINT [\"/usr/local/bin/nixpacks-init\", \"--\"]"));
+        assert!(dockerfile.contains("CMD [\"node\", \"index.js\", \"--name\", \"my app\"]"));
+    }
+
+    #[test]
+    fn test_start_phase_with_shell() {
+        let mut start = StartPhase::new("npm run start");
+        start.shell = Some(vec!["/bin/sh".to_string(), "-c".to_string()]);
+
+        let generate = |start: &StartPhase| {
+            start
+                .generate_dockerfile(
+                    &DockerBuilderOptions::default(),
+                    &Environment::default(),
+                    &OutputDir::default(),
+                    None,
+                )
+                .unwrap()
+        };
+
+        let dockerfile = generate(&start);
+        assert!(dockerfile.contains("ENTRYPOINT [\"/bin/sh\", \"-c\"]"));
+        assert!(dockerfile.contains("CMD [\"npm run start\"]"));
+
+        start.add_process("web", "npm run start");
+        assert!(generate(&start)
+            .contains("ENTRYPOINT [\"/bin/sh\", \"/usr/local/bin/nixpacks-start\"]"));
+    }
 }
@@ -1,4 +1,7 @@
 use super::ImageBuilder;
+use anyhow::{Context, Result};
+use cache::{parse_cache_backends, CacheBackend};
+use std::collections::BTreeMap;
 
 #[derive(Clone, Default, Debug)]
 #[allow(clippy::struct_excessive_bools)]
@@ -12,15 +15,68 @@ pub struct DockerBuilderOptions {
     pub cache_key: Option<String>,
     pub no_cache: bool,
     pub inline_cache: bool,
-    pub cache_from: Option<String>,
+    /// Caches to import, as images or `type=registry|local,...`
+    pub cache_from: Vec<String>,
+    /// Caches to export, as images or `type=registry|local|inline,...`
+    pub cache_to: Vec<String>,
     pub platform: Vec<String>,
     pub current_dir: bool,
     pub no_error_without_start: bool,
     pub incremental_cache_image: Option<String>,
     pub verbose: bool,
+    /// Run all the phases in one stage instead of a stage per phase
+    pub single_stage: bool,
+    /// Names of the secrets to pass from the host environment
+    pub secrets: Vec<String>,
+    /// Secrets to pass from files, in the form `NAME=PATH`
+    pub secret_files: Vec<String>,
+    /// Which builder creates the image, `docker`, `podman`, `buildah` or `oci`.
+    /// The first of Docker, Podman and Buildah that is installed is used when this is not set.
+    pub builder: Option<String>,
+    /// Tarball of the root filesystem that the OCI builder puts the app on top of
+    pub base_rootfs: Option<String>,
+    /// Write a `docker-bake.json` to the output directory that builds the image with `docker buildx bake`
+    pub bake: bool,
+    /// Push the name and all the tags of the image after it is built
+    pub push: bool,
+    /// Where to write the digests of the pushed image
+    pub digest_file: Option<String>,
+    /// Where to write a JSON file with the id, tags, digests and providers of the image
+    pub metadata_file: Option<String>,
 }
 
-mod cache;
+impl DockerBuilderOptions {
+    pub fn get_cache_from(&self) -> Result<Vec<CacheBackend>> {
+        parse_cache_backends(&self.cache_from)
+    }
+
+    /// The caches to export, including the inline cache of `inline_cache`
+    pub fn get_cache_to(&self) -> Result<Vec<CacheBackend>> {
+        let mut caches = parse_cache_backends(&self.cache_to)?;
+        if self.inline_cache && !caches.contains(&CacheBackend::Inline) {
+            caches.push(CacheBackend::Inline);
+        }
+        Ok(caches)
+    }
+
+    /// The labels that were passed in as `KEY=VALUE`
+    pub fn get_labels(&self) -> Result<BTreeMap<String, String>> {
+        self.labels
+            .iter()
+            .map(|label| {
+                label
+                    .split_once('=')
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .with_context(|| format!("Label `{label}` should be in the form KEY=VALUE"))
+            })
+            .collect()
+    }
+}
+
+pub mod bake;
+pub mod build_metadata;
+pub mod build_tool;
+pub mod cache;
 pub mod docker_image_builder;
 mod dockerfile_generation;
 pub mod file_server;
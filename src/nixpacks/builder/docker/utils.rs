@@ -1,4 +1,16 @@
 use super::cache::sanitize_cache_key;
+use crate::nixpacks::{
+    environment::EnvironmentVariables,
+    plan::phase::{Healthcheck, Processes, INIT_PATH},
+};
+use anyhow::{bail, Result};
+use std::fmt::Write;
+
+/// Where the packages installed with Nix are linked to for other users and exec form commands
+const NIX_PROFILE_LINK: &str = "/nix/var/nix/profiles/nixpacks";
+
+/// Where BuildKit mounts the secrets for a command
+const SECRETS_DIR: &str = "/run/secrets/";
 
 pub fn get_cache_mount(
     cache_key: &Option<String>,
@@ -24,6 +36,77 @@ pub fn get_cache_mount(
     }
 }
 
+pub fn get_secret_mount(secrets: &Option<Vec<String>>) -> String {
+    secrets
+        .iter()
+        .flatten()
+        .map(|name| format!("--mount=type=secret,id={name}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Shell prefix for the commands of a phase that exports its variables and mounted secrets
+pub fn get_phase_env_prefix(
+    variables: &Option<EnvironmentVariables>,
+    secrets: &Option<Vec<String>>,
+) -> String {
+    let exports = variables
+        .iter()
+        .flatten()
+        .map(|(name, value)| format!("{name}='{}'", value.replace('\'', "'\\''")))
+        .chain(
+            secrets
+                .iter()
+                .flatten()
+                .map(|name| format!("{name}=\"$(cat {SECRETS_DIR}{name})\"")),
+        )
+        .collect::<Vec<_>>();
+
+    if exports.is_empty() {
+        String::new()
+    } else {
+        format!("export {} && ", exports.join(" "))
+    }
+}
+
+/// Declare the variables as build arguments, which are not saved in the image
+pub fn get_arg_command(variables: &[String]) -> String {
+    if variables.is_empty() {
+        String::new()
+    } else {
+        format!("ARG {}", variables.join(" "))
+    }
+}
+
+/// Pull the variables in from `--build-arg` and save them in the environment of the image
+pub fn get_env_command(variables: &[String]) -> String {
+    if variables.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "{}\nENV {}",
+            get_arg_command(variables),
+            variables
+                .iter()
+                .map(|name| format!("{name}=${name}"))
+                .collect::<Vec<_>>()
+                .join(" ")
+        )
+    }
+}
+
+/// Add the paths to `PATH`, also for login shells which reset it from `/etc/profile`
+pub fn get_path_command(paths: &[String]) -> String {
+    if paths.is_empty() {
+        String::new()
+    } else {
+        let joined_paths = paths.join(":");
+        format!(
+            "ENV PATH {joined_paths}:$PATH\nRUN printf '\\nPATH={joined_paths}:$PATH' >> /root/.profile"
+        )
+    }
+}
+
 pub fn get_copy_command(files: &[String], app_dir: &str) -> String {
     if files.is_empty() {
         String::new()
@@ -34,7 +117,7 @@ pub fn get_copy_command(files: &[String], app_dir: &str) -> String {
 
 pub fn get_copy_from_command(from: &str, files: &[String], app_dir: &str) -> String {
     if files.is_empty() {
-        format!("COPY --from=0 {} {}", app_dir, app_dir)
+        format!("COPY --from={} {} {}", from, app_dir, app_dir)
     } else {
         format!(
             "COPY --from={} {} {}",
@@ -49,12 +132,130 @@ pub fn get_copy_from_command(from: &str, files: &[String], app_dir: &str) -> Str
     }
 }
 
+/// A JSON array of the values, as used by the exec form of `CMD` and `ENTRYPOINT`
+pub fn get_exec_form(values: &[String]) -> String {
+    let values = values
+        .iter()
+        .map(|value| serde_json::to_string(value).unwrap_or_default())
+        .collect::<Vec<_>>();
+
+    format!("[{}]", values.join(", "))
+}
+
+/// Link root's Nix profile to a place that every user can read and put it on the `PATH`,
+/// for start commands that are not run by root's login shell
+pub fn get_nix_profile_path_command() -> String {
+    format!(
+        "RUN ln -sfn \"$(readlink -f /root/.nix-profile)\" {NIX_PROFILE_LINK}\nENV PATH={NIX_PROFILE_LINK}/bin:$PATH"
+    )
+}
+
+/// Copy the binary of the init out of the Nix profile, so it can also be copied into a run image
+pub fn get_copy_init_command(init: &str) -> String {
+    format!("RUN cp \"$(readlink -f \"$(command -v {init})\")\" {INIT_PATH}")
+}
+
 pub fn get_exec_command(command: &str) -> String {
     let params = command.replace('\"', "\\\"");
 
     format!("CMD [\"{}\"]", params)
 }
 
+/// Create the user if the image does not have it yet, and give it the app and the writable directories.
+/// New users get root's profile so that login shells find the same packages.
+///
+/// Users are created with `useradd`, which Debian and Ubuntu based images have. The build stops
+/// with an error in images without it, e.g. Alpine, unless they already have the user.
+pub fn get_create_user_command(user: &str, writable_directories: &[String]) -> String {
+    let dirs = writable_directories
+        .iter()
+        .map(|dir| {
+            if dir.starts_with('/') {
+                dir.clone()
+            } else {
+                format!("/app/{dir}")
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let create_user = format!(
+        "(id -u {user} >/dev/null 2>&1 || ((command -v useradd >/dev/null || (echo 'Creating the user {user} needs useradd in the image' >&2 && exit 1)) && useradd --create-home --user-group --shell /bin/bash {user} && (! [ -f /root/.profile ] || cp /root/.profile /home/{user}/.profile)))"
+    );
+
+    if dirs.is_empty() {
+        format!("RUN {create_user} && chown -R {user} /app")
+    } else {
+        let dirs = dirs.join(" ");
+        format!("RUN {create_user} && mkdir -p {dirs} && chown -R {user} /app {dirs}")
+    }
+}
+
+pub fn get_expose_command(ports: &Option<Vec<u16>>) -> String {
+    match ports {
+        Some(ports) if !ports.is_empty() => format!(
+            "EXPOSE {}",
+            ports
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(" ")
+        ),
+        _ => String::new(),
+    }
+}
+
+/// The `HEALTHCHECK` instruction for the image, or nothing if the healthcheck has no command
+pub fn get_healthcheck_command(healthcheck: &Option<Healthcheck>) -> String {
+    let healthcheck = match healthcheck {
+        Some(healthcheck) => healthcheck,
+        None => return String::new(),
+    };
+    let cmd = match healthcheck.get_cmd() {
+        Some(cmd) => cmd,
+        None => return String::new(),
+    };
+
+    let options = [
+        ("interval", healthcheck.interval.clone()),
+        ("timeout", healthcheck.timeout.clone()),
+        ("start-period", healthcheck.start_period.clone()),
+        (
+            "retries",
+            healthcheck.retries.map(|retries| retries.to_string()),
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| value.map(|value| format!("--{name}={value} ")))
+    .collect::<String>();
+
+    format!("HEALTHCHECK {options}CMD {cmd}")
+}
+
+/// Shell script that starts the named process given as the first argument.
+/// Anything that is not a process name is run as a command.
+///
+/// Processes are `exec`ed so that they replace the launcher and get the signals sent to the container.
+/// They run through `bash -c` so that commands like `migrate && serve` still run every part.
+pub fn get_process_launcher(processes: &Processes) -> Result<String> {
+    let cases = processes
+        .iter()
+        .try_fold(String::new(), |mut cases, (name, cmd)| {
+            if name.contains(['\'', '"']) {
+                bail!("Process name `{name}` can not contain quotes");
+            }
+            write!(
+                cases,
+                "  '{name}')\n    exec bash -c {}\n    ;;\n",
+                shell_words::quote(cmd)
+            )?;
+            Ok(cases)
+        })?;
+
+    Ok(format!(
+        "#!/bin/bash\ncase \"$1\" in\n{cases}  *)\n    eval \"$@\"\n    ;;\nesac\n"
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -81,6 +282,25 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn test_get_phase_env_prefix() {
+        let variables = Some(EnvironmentVariables::from([(
+            "GREETING".to_string(),
+            "it's $HOME".to_string(),
+        )]));
+        let secrets = Some(vec!["NPM_TOKEN".to_string()]);
+
+        assert_eq!(String::new(), get_phase_env_prefix(&None, &None));
+        assert_eq!(
+            "--mount=type=secret,id=NPM_TOKEN",
+            get_secret_mount(&secrets)
+        );
+        assert_eq!(
+            "export GREETING='it'\\''s $HOME' NPM_TOKEN=\"$(cat /run/secrets/NPM_TOKEN)\" && ",
+            get_phase_env_prefix(&variables, &secrets)
+        );
+    }
+
     #[test]
     fn test_get_copy_command() {
         let files = vec!["file1".to_string(), "file2".to_string()];
@@ -100,7 +320,7 @@ mod tests {
         let app_dir = "app";
 
         assert_eq!(
-            format!("COPY --from=0 {} {}", app_dir, app_dir),
+            format!("COPY --from={} {} {}", from, app_dir, app_dir),
             get_copy_from_command(from, &[], app_dir)
         );
         assert_eq!(
@@ -126,4 +346,72 @@ mod tests {
             get_exec_command("command1 command2 -l \"asdf\"")
         );
     }
+
+    #[test]
+    fn test_get_create_user_command() {
+        assert_eq!(
+            get_create_user_command("app", &["tmp".to_string(), "/var/log/nginx".to_string()]),
+            "RUN (id -u app >/dev/null 2>&1 || ((command -v useradd >/dev/null || (echo 'Creating the user app needs useradd in the image' >&2 && exit 1)) && useradd --create-home --user-group --shell /bin/bash app && (! [ -f /root/.profile ] || cp /root/.profile /home/app/.profile))) && mkdir -p /app/tmp /var/log/nginx && chown -R app /app /app/tmp /var/log/nginx"
+        );
+        assert!(get_create_user_command("app", &[]).ends_with(" && chown -R app /app"));
+    }
+
+    #[test]
+    fn test_get_expose_command() {
+        assert_eq!(get_expose_command(&None), "");
+        assert_eq!(get_expose_command(&Some(vec![80, 443])), "EXPOSE 80 443");
+    }
+
+    #[test]
+    fn test_get_healthcheck_command() {
+        assert_eq!(get_healthcheck_command(&None), "");
+        assert_eq!(get_healthcheck_command(&Some(Healthcheck::default())), "");
+
+        let mut healthcheck = Healthcheck::http("up", 3000);
+        healthcheck.interval = Some("10s".to_string());
+        healthcheck.retries = Some(5);
+        assert_eq!(
+            get_healthcheck_command(&Some(healthcheck)),
+            "HEALTHCHECK --interval=10s --retries=5 CMD curl -fsS http://localhost:${PORT:-3000}/up || exit 1"
+        );
+
+        let healthcheck = Healthcheck {
+            cmd: Some("./healthcheck.sh".to_string()),
+            path: Some("/".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            get_healthcheck_command(&Some(healthcheck)),
+            "HEALTHCHECK CMD ./healthcheck.sh"
+        );
+    }
+
+    #[test]
+    fn test_get_exec_form() {
+        assert_eq!(get_exec_form(&[]), "[]");
+        assert_eq!(
+            get_exec_form(&["echo".to_string(), "say \"hi\"".to_string()]),
+            "[\"echo\", \"say \\\"hi\\\"\"]"
+        );
+    }
+
+    #[test]
+    fn test_get_process_launcher() {
+        let processes = Processes::from([
+            ("web".to_string(), "npm run start".to_string()),
+            ("worker".to_string(), "npm run worker".to_string()),
+        ]);
+
+        assert_eq!(
+            get_process_launcher(&processes).unwrap(),
+            "#!/bin/bash\ncase \"$1\" in\n  'web')\n    exec bash -c 'npm run start'\n    ;;\n  'worker')\n    exec bash -c 'npm run worker'\n    ;;\n  *)\n    eval \"$@\"\n    ;;\nesac\n"
+        );
+    }
+
+    #[test]
+    fn test_get_process_launcher_rejects_quotes() {
+        let processes = Processes::from([("it's".to_string(), "npm run start".to_string())]);
+
+        assert!(get_process_launcher(&processes).is_err());
+    }
 }
@@ -2,6 +2,7 @@ use super::{environment::Environment, plan::BuildPlan};
 use anyhow::Result;
 
 pub mod docker;
+pub mod oci;
 
 pub trait ImageBuilder {
     fn create_image(&self, app_source: &str, plan: &BuildPlan, env: &Environment) -> Result<()>;
@@ -0,0 +1,167 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+pub const MEDIA_TYPE_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+pub const MEDIA_TYPE_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+pub const MEDIA_TYPE_CONFIG: &str = "application/vnd.oci.image.config.v1+json";
+pub const MEDIA_TYPE_LAYER: &str = "application/vnd.oci.image.layer.v1.tar";
+pub const MEDIA_TYPE_LAYER_GZIP: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
+
+/// Annotation of the index that holds the name an image is tagged with
+pub const REF_NAME_ANNOTATION: &str = "org.opencontainers.image.ref.name";
+
+const IMAGE_LAYOUT_VERSION: &str = "1.0.0";
+
+/// Points to a blob of the layout, https://github.com/opencontainers/image-spec/blob/main/descriptor.md
+#[serde_with::skip_serializing_none]
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Descriptor {
+    pub media_type: String,
+    pub digest: String,
+    pub size: u64,
+    pub annotations: Option<BTreeMap<String, String>>,
+}
+
+/// https://github.com/opencontainers/image-spec/blob/main/config.md
+#[serde_with::skip_serializing_none]
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ImageConfig {
+    pub architecture: String,
+    pub os: String,
+    pub variant: Option<String>,
+    pub config: ContainerConfig,
+    pub rootfs: RootFs,
+}
+
+/// How containers that are started from the image run
+#[serde_with::skip_serializing_none]
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerConfig {
+    pub user: Option<String>,
+    pub exposed_ports: Option<BTreeMap<String, BTreeMap<String, String>>>,
+    pub env: Vec<String>,
+    pub entrypoint: Option<Vec<String>>,
+    pub cmd: Option<Vec<String>>,
+    pub working_dir: Option<String>,
+    pub labels: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct RootFs {
+    #[serde(rename = "type")]
+    pub fs_type: String,
+    /// Digests of the uncompressed layers
+    pub diff_ids: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub schema_version: u32,
+    pub media_type: String,
+    pub config: Descriptor,
+    pub layers: Vec<Descriptor>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Index {
+    pub schema_version: u32,
+    pub media_type: String,
+    pub manifests: Vec<Descriptor>,
+}
+
+/// A directory in the OCI image layout, https://github.com/opencontainers/image-spec/blob/main/image-layout.md
+pub struct ImageLayout {
+    pub root: PathBuf,
+}
+
+impl ImageLayout {
+    pub fn create<P: AsRef<Path>>(root: P) -> Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(root.join("blobs").join("sha256"))
+            .context("Creating OCI layout directory")?;
+        fs::write(
+            root.join("oci-layout"),
+            format!("{{\"imageLayoutVersion\":\"{IMAGE_LAYOUT_VERSION}\"}}"),
+        )
+        .context("Writing oci-layout")?;
+
+        Ok(ImageLayout { root })
+    }
+
+    /// Copy the file into the blobs of the layout
+    pub fn add_blob_file(&self, media_type: &str, path: &Path) -> Result<Descriptor> {
+        let digest = get_digest(File::open(path)?)?;
+        let size = fs::copy(path, self.get_blob_path(&digest))
+            .context(format!("Copying {} into the OCI layout", path.display()))?;
+
+        Ok(Descriptor {
+            media_type: media_type.to_string(),
+            digest,
+            size,
+            annotations: None,
+        })
+    }
+
+    /// Serialize the value as JSON into the blobs of the layout
+    pub fn add_json_blob<T: Serialize>(&self, media_type: &str, value: &T) -> Result<Descriptor> {
+        let data = serde_json::to_vec(value)?;
+        let digest = get_digest(data.as_slice())?;
+        fs::write(self.get_blob_path(&digest), &data)?;
+
+        Ok(Descriptor {
+            media_type: media_type.to_string(),
+            digest,
+            size: data.len() as u64,
+            annotations: None,
+        })
+    }
+
+    /// Write the `index.json` that lists the manifests of the layout
+    pub fn write_index(&self, manifests: Vec<Descriptor>) -> Result<()> {
+        let index = Index {
+            schema_version: 2,
+            media_type: MEDIA_TYPE_INDEX.to_string(),
+            manifests,
+        };
+        fs::write(self.root.join("index.json"), serde_json::to_vec(&index)?)
+            .context("Writing index.json")
+    }
+
+    /// Path of the blob with the given `sha256:` digest
+    pub fn get_blob_path(&self, digest: &str) -> PathBuf {
+        self.root
+            .join("blobs")
+            .join("sha256")
+            .join(digest.trim_start_matches("sha256:"))
+    }
+
+    /// Pack the layout into a tarball, which can be loaded with `docker load` or `podman load`
+    pub fn write_tar(&self, path: &Path) -> Result<()> {
+        let mut tar = tar::Builder::new(File::create(path)?);
+        tar.mode(tar::HeaderMode::Deterministic);
+        for file in ["oci-layout", "index.json"] {
+            tar.append_path_with_name(self.root.join(file), file)?;
+        }
+        tar.append_dir_all("blobs", self.root.join("blobs"))?;
+        tar.into_inner()?.flush()?;
+        Ok(())
+    }
+}
+
+/// The `sha256:` digest of everything that is read from `reader`
+pub fn get_digest<R: Read>(mut reader: R) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(format!("sha256:{:x}", hasher.finalize()))
+}
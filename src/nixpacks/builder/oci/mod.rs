@@ -0,0 +1,2 @@
+pub mod image_layout;
+pub mod oci_image_builder;
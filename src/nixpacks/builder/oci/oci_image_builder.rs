@@ -0,0 +1,463 @@
+use super::image_layout::{
+    get_digest, ContainerConfig, Descriptor, ImageConfig, ImageLayout, Manifest, RootFs,
+    MEDIA_TYPE_CONFIG, MEDIA_TYPE_LAYER, MEDIA_TYPE_LAYER_GZIP, MEDIA_TYPE_MANIFEST,
+    REF_NAME_ANNOTATION,
+};
+use crate::nixpacks::{
+    app::ASSETS_DIR,
+    builder::{
+        docker::{utils, DockerBuilderOptions},
+        ImageBuilder,
+    },
+    environment::{Environment, VariableScope},
+    files,
+    logger::Logger,
+    plan::{
+        phase::{StartPhase, PROCESS_LAUNCHER},
+        BuildPlan,
+    },
+};
+use anyhow::{bail, Context, Result};
+use flate2::read::GzDecoder;
+use std::{
+    collections::BTreeMap,
+    env::consts,
+    fs::File,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+use tempdir::TempDir;
+
+const APP_DIR: &str = "/app";
+const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Builds an OCI image without a container runtime, by putting the app on top of a base root filesystem.
+///
+/// Nothing is run while building, so the packages and build output of the phases have to be in the base.
+pub struct OciImageBuilder {
+    logger: Logger,
+    options: DockerBuilderOptions,
+}
+
+impl ImageBuilder for OciImageBuilder {
+    fn create_image(&self, app_src: &str, plan: &BuildPlan, _env: &Environment) -> Result<()> {
+        let mut plan = plan.clone();
+        plan.unescape_variables();
+        let plan = &plan;
+
+        let base_rootfs = match &self.options.base_rootfs {
+            Some(base_rootfs) => PathBuf::from(base_rootfs),
+            None => bail!("The OCI builder needs a base root filesystem, pass a tarball of one with --base-rootfs"),
+        };
+        let out = match &self.options.out_dir {
+            Some(out) => PathBuf::from(out),
+            None => bail!("The OCI builder needs a place to write the image to, pass a directory or `.tar` file with --out"),
+        };
+
+        let start = plan.start_phase.clone().unwrap_or_default();
+        if start.cmd.is_none() && !start.has_processes() && !self.options.no_error_without_start {
+            bail!("No start command could be found")
+        }
+        if start.init.is_some() && start.entrypoint.is_none() {
+            bail!("The OCI builder can not install an init, set `start.entrypoint` to one that is in the base root filesystem instead");
+        }
+        if start.user.is_some() {
+            bail!("The OCI builder can not create the user of `start.user`, use a base root filesystem that runs as that user instead");
+        }
+
+        println!("{}", plan.get_build_string()?);
+
+        let phases = plan.phases.clone().unwrap_or_default();
+        if !phases.is_empty() {
+            self.logger.log_step(&format!(
+                "Phases are not run by the OCI builder, the base root filesystem needs to provide {}",
+                phases.keys().cloned().collect::<Vec<_>>().join(", ")
+            ));
+        }
+
+        let tmp = TempDir::new("nixpacks-oci").context("Creating a temp directory")?;
+        let is_tar = out.extension().map_or(false, |ext| ext == "tar");
+        let layout = if is_tar {
+            ImageLayout::create(tmp.path().join("layout"))?
+        } else {
+            ImageLayout::create(&out)?
+        };
+
+        self.logger.log_step("Adding base root filesystem");
+        let (base_layer, base_diff_id) = add_base_layer(&layout, &base_rootfs)?;
+
+        self.logger.log_step("Adding app");
+        let app_tar = tmp.path().join("app.tar");
+        write_app_layer(app_src, plan, &app_tar)?;
+        let app_layer = layout.add_blob_file(MEDIA_TYPE_LAYER, &app_tar)?;
+        let app_diff_id = app_layer.digest.clone();
+
+        let config = self.get_image_config(plan, vec![base_diff_id, app_diff_id])?;
+        let manifest = Manifest {
+            schema_version: 2,
+            media_type: MEDIA_TYPE_MANIFEST.to_string(),
+            config: layout.add_json_blob(MEDIA_TYPE_CONFIG, &config)?,
+            layers: vec![base_layer, app_layer],
+        };
+        let manifest = layout.add_json_blob(MEDIA_TYPE_MANIFEST, &manifest)?;
+        layout.write_index(self.get_tagged_manifests(&manifest))?;
+
+        if is_tar {
+            layout
+                .write_tar(&out)
+                .context(format!("Writing {}", out.display()))?;
+        }
+
+        self.logger.log_section("Successfully Built!");
+        println!("\nSaved image {} to:", manifest.digest);
+        println!("  {}", out.display());
+
+        Ok(())
+    }
+}
+
+impl OciImageBuilder {
+    pub fn new(logger: Logger, options: DockerBuilderOptions) -> OciImageBuilder {
+        OciImageBuilder { logger, options }
+    }
+
+    fn get_image_config(&self, plan: &BuildPlan, diff_ids: Vec<String>) -> Result<ImageConfig> {
+        let start = plan.start_phase.clone().unwrap_or_default();
+
+        let mut paths = plan
+            .phases
+            .clone()
+            .unwrap_or_default()
+            .into_values()
+            .flat_map(|phase| phase.paths.unwrap_or_default())
+            .collect::<Vec<_>>();
+        paths.push(DEFAULT_PATH.to_string());
+
+        let mut env = vec![format!("PATH={}", paths.join(":"))];
+        env.extend(
+            plan.variables
+                .clone()
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|(name, _)| plan.get_variable_scope(name) != VariableScope::Build)
+                .map(|(name, value)| format!("{name}={value}")),
+        );
+
+        let exposed_ports = start.ports.as_ref().map(|ports| {
+            ports
+                .iter()
+                .map(|port| (format!("{port}/tcp"), BTreeMap::new()))
+                .collect()
+        });
+
+        let labels = self.options.get_labels()?;
+
+        let (os, architecture, variant) = self.get_platform()?;
+
+        Ok(ImageConfig {
+            architecture,
+            os,
+            variant,
+            config: ContainerConfig {
+                user: start.user.clone(),
+                exposed_ports,
+                env,
+                entrypoint: Some(get_entrypoint(&start)),
+                cmd: Some(start.get_command()?),
+                working_dir: Some(APP_DIR.to_string()),
+                labels: if labels.is_empty() {
+                    None
+                } else {
+                    Some(labels)
+                },
+            },
+            rootfs: RootFs {
+                fs_type: "layers".to_string(),
+                diff_ids,
+            },
+        })
+    }
+
+    /// The os, architecture and variant of the first `--platform`, or else of the host
+    fn get_platform(&self) -> Result<(String, String, Option<String>)> {
+        if let Some(platform) = self.options.platform.first() {
+            let parts = platform.split('/').collect::<Vec<_>>();
+            return match *parts.as_slice() {
+                [os, arch] => Ok((os.to_string(), arch.to_string(), None)),
+                [os, arch, variant] => {
+                    Ok((os.to_string(), arch.to_string(), Some(variant.to_string())))
+                }
+                _ => {
+                    bail!("Platform `{platform}` should be in the form os/arch or os/arch/variant")
+                }
+            };
+        }
+
+        let architecture = match consts::ARCH {
+            "x86_64" => "amd64",
+            "aarch64" => "arm64",
+            "x86" => "386",
+            arch => arch,
+        };
+        Ok(("linux".to_string(), architecture.to_string(), None))
+    }
+
+    /// A manifest descriptor for the name and every tag of the image
+    fn get_tagged_manifests(&self, manifest: &Descriptor) -> Vec<Descriptor> {
+        let refs = self
+            .options
+            .name
+            .iter()
+            .chain(self.options.tags.iter())
+            .collect::<Vec<_>>();
+
+        if refs.is_empty() {
+            return vec![manifest.clone()];
+        }
+
+        refs.into_iter()
+            .map(|name| Descriptor {
+                annotations: Some(BTreeMap::from([(
+                    REF_NAME_ANNOTATION.to_string(),
+                    name.clone(),
+                )])),
+                ..manifest.clone()
+            })
+            .collect()
+    }
+}
+
+/// Named processes are started through the launcher, like in the images that are built by Docker
+fn get_entrypoint(start: &StartPhase) -> Vec<String> {
+    if start.has_processes() {
+        start.get_launcher_entrypoint(PROCESS_LAUNCHER)
+    } else {
+        start.get_entrypoint()
+    }
+}
+
+/// Add the base root filesystem as the first layer, with the digest of its uncompressed contents
+fn add_base_layer(layout: &ImageLayout, base_rootfs: &Path) -> Result<(Descriptor, String)> {
+    let mut magic = [0; 2];
+    File::open(base_rootfs)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .context(format!(
+            "Reading base root filesystem {}",
+            base_rootfs.display()
+        ))?;
+
+    if magic == GZIP_MAGIC {
+        let layer = layout.add_blob_file(MEDIA_TYPE_LAYER_GZIP, base_rootfs)?;
+        let diff_id = get_digest(GzDecoder::new(File::open(base_rootfs)?))?;
+        Ok((layer, diff_id))
+    } else {
+        let layer = layout.add_blob_file(MEDIA_TYPE_LAYER, base_rootfs)?;
+        let diff_id = layer.digest.clone();
+        Ok((layer, diff_id))
+    }
+}
+
+/// Write a layer with the app in `/app`, the static assets and the process launcher
+fn write_app_layer(app_src: &str, plan: &BuildPlan, path: &Path) -> Result<()> {
+    let mut tar = tar::Builder::new(File::create(path)?);
+    tar.mode(tar::HeaderMode::Deterministic);
+    tar.follow_symlinks(false);
+
+    let app_dir = APP_DIR.trim_start_matches('/');
+    let excludes = files::get_ignore_patterns(app_src, &plan.exclude.clone().unwrap_or_default())?;
+    for entry in files::walk_dir(app_src, &excludes)? {
+        let entry = entry?;
+        let name = Path::new(app_dir).join(entry.path().strip_prefix(app_src)?);
+        tar.append_path_with_name(entry.path(), &name)
+            .context(format!(
+                "Adding {} to the app layer",
+                entry.path().display()
+            ))?;
+    }
+
+    let assets_dir = ASSETS_DIR.trim_matches('/');
+    for (name, content) in plan.static_assets.clone().unwrap_or_default() {
+        append_file(&mut tar, &format!("{assets_dir}/{name}"), &content, 0o644)?;
+    }
+
+    let start = plan.start_phase.clone().unwrap_or_default();
+    if start.has_processes() {
+        let launcher = utils::get_process_launcher(&start.processes.unwrap_or_default())?;
+        append_file(
+            &mut tar,
+            PROCESS_LAUNCHER.trim_start_matches('/'),
+            &launcher,
+            0o755,
+        )?;
+    }
+
+    tar.into_inner()?.flush()?;
+    Ok(())
+}
+
+fn append_file<W: Write>(
+    tar: &mut tar::Builder<W>,
+    name: &str,
+    content: &str,
+    mode: u32,
+) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(mode);
+    header.set_cksum();
+    tar.append_data(&mut header, name, content.as_bytes())
+        .context(format!("Adding {name} to the app layer"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use serde_json::Value;
+    use std::fs;
+
+    fn read_blob(layout: &Path, descriptor: &Value) -> Vec<u8> {
+        let digest = descriptor["digest"].as_str().unwrap();
+        let data = fs::read(layout.join("blobs/sha256").join(&digest[7..])).unwrap();
+        assert_eq!(get_digest(data.as_slice()).unwrap(), digest);
+        assert_eq!(descriptor["size"].as_u64(), Some(data.len() as u64));
+        data
+    }
+
+    #[test]
+    fn test_create_oci_image() -> Result<()> {
+        let tmp = TempDir::new("nixpacks-oci-test")?;
+
+        let rootfs = tmp.path().join("rootfs.tar.gz");
+        let mut base =
+            tar::Builder::new(GzEncoder::new(File::create(&rootfs)?, Compression::fast()));
+        append_file(&mut base, "bin/hello", "#!/bin/sh\necho hello\n", 0o755)?;
+        base.into_inner()?.finish()?;
+
+        let app = tmp.path().join("app");
+        fs::create_dir_all(app.join("node_modules"))?;
+        fs::write(app.join("index.js"), "console.log('hi')")?;
+        fs::write(app.join("node_modules/dep.js"), "")?;
+
+        let plan = BuildPlan::from_toml(
+            r#"
+            exclude = ["node_modules"]
+
+            [variables]
+            NODE_ENV = "production"
+
+            [phases.setup]
+            nixPkgs = ["nodejs"]
+            paths = ["/app/node_modules/.bin"]
+
+            [start]
+            cmd = "node index.js"
+            exec = true
+            ports = [3000]
+            "#,
+        )?;
+
+        let layout = tmp.path().join("image");
+        let options = DockerBuilderOptions {
+            name: Some("app".to_string()),
+            tags: vec!["app:v1".to_string()],
+            labels: vec!["team=web".to_string()],
+            platform: vec!["linux/arm64".to_string()],
+            base_rootfs: Some(rootfs.to_str().unwrap().to_string()),
+            out_dir: Some(layout.to_str().unwrap().to_string()),
+            ..Default::default()
+        };
+        let builder = OciImageBuilder::new(Logger::new(), options.clone());
+        builder.create_image(app.to_str().unwrap(), &plan, &Environment::default())?;
+
+        let index: Value = serde_json::from_slice(&fs::read(layout.join("index.json"))?)?;
+        let manifests = index["manifests"].as_array().unwrap();
+        assert_eq!(manifests.len(), 2);
+        assert_eq!(
+            manifests[1]["annotations"][REF_NAME_ANNOTATION].as_str(),
+            Some("app:v1")
+        );
+
+        let manifest: Value = serde_json::from_slice(&read_blob(&layout, &manifests[0]))?;
+        let layers = manifest["layers"].as_array().unwrap();
+        assert_eq!(layers[0]["mediaType"].as_str(), Some(MEDIA_TYPE_LAYER_GZIP));
+
+        let config: Value = serde_json::from_slice(&read_blob(&layout, &manifest["config"]))?;
+        assert_eq!(config["architecture"].as_str(), Some("arm64"));
+        assert_eq!(
+            config["config"]["Cmd"],
+            serde_json::json!(["node", "index.js"])
+        );
+        assert_eq!(config["config"]["Entrypoint"], serde_json::json!([]));
+        assert_eq!(config["config"]["Labels"]["team"].as_str(), Some("web"));
+        assert!(config["config"]["ExposedPorts"]["3000/tcp"].is_object());
+        let env = config["config"]["Env"].as_array().unwrap();
+        assert!(env.contains(&Value::from(format!(
+            "PATH=/app/node_modules/.bin:{DEFAULT_PATH}"
+        ))));
+        assert!(env.contains(&Value::from("NODE_ENV=production")));
+
+        // The diff id of the compressed base is the digest of the uncompressed tar
+        let diff_ids = config["rootfs"]["diff_ids"].as_array().unwrap();
+        assert_ne!(diff_ids[0], layers[0]["digest"]);
+        assert_eq!(diff_ids[1], layers[1]["digest"]);
+
+        let app_layer = read_blob(&layout, &layers[1]);
+        let names = tar::Archive::new(app_layer.as_slice())
+            .entries()?
+            .map(|entry| Ok(entry?.path()?.to_string_lossy().to_string()))
+            .collect::<Result<Vec<_>>>()?;
+        assert!(names.contains(&"app/index.js".to_string()));
+        assert!(!names.iter().any(|name| name.contains("node_modules")));
+
+        // The same image is written as a tarball of the layout
+        let tarball = tmp.path().join("image.tar");
+        let options = DockerBuilderOptions {
+            out_dir: Some(tarball.to_str().unwrap().to_string()),
+            ..options
+        };
+        OciImageBuilder::new(Logger::new(), options).create_image(
+            app.to_str().unwrap(),
+            &plan,
+            &Environment::default(),
+        )?;
+        let names = tar::Archive::new(File::open(&tarball)?)
+            .entries()?
+            .map(|entry| Ok(entry?.path()?.to_string_lossy().to_string()))
+            .collect::<Result<Vec<_>>>()?;
+        assert!(names.contains(&"index.json".to_string()));
+        assert!(names.contains(&format!(
+            "blobs/sha256/{}",
+            &manifests[0]["digest"].as_str().unwrap()[7..]
+        )));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_oci_image_with_user() -> Result<()> {
+        let tmp = TempDir::new("nixpacks-oci-user-test")?;
+        let rootfs = tmp.path().join("rootfs.tar");
+        tar::Builder::new(File::create(&rootfs)?).finish()?;
+
+        let mut start = StartPhase::new("node index.js");
+        start.user = Some("app".to_string());
+        let mut plan = BuildPlan::default();
+        plan.set_start_phase(start);
+
+        let options = DockerBuilderOptions {
+            base_rootfs: Some(rootfs.to_str().unwrap().to_string()),
+            out_dir: Some(tmp.path().join("image").to_str().unwrap().to_string()),
+            ..Default::default()
+        };
+        let result = OciImageBuilder::new(Logger::new(), options).create_image(
+            tmp.path().to_str().unwrap(),
+            &plan,
+            &Environment::default(),
+        );
+        assert!(result.unwrap_err().to_string().contains("start.user"));
+
+        Ok(())
+    }
+}
@@ -1,39 +1,99 @@
 use anyhow::Result;
 use regex::Regex;
-use std::{collections::BTreeMap, env};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    env,
+    fmt::{self, Display},
+};
 
 pub type EnvironmentVariables = BTreeMap<String, String>;
+pub type VariableScopes = BTreeMap<String, VariableScope>;
+
+/// Where a variable is available
+#[derive(PartialEq, Eq, Serialize, Deserialize, Clone, Copy, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum VariableScope {
+    /// Only while the image is built
+    Build,
+    /// Only in containers that are started from the image
+    Runtime,
+    /// While building and in running containers
+    Both,
+}
+
+impl Default for VariableScope {
+    fn default() -> Self {
+        VariableScope::Both
+    }
+}
+
+impl Display for VariableScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VariableScope::Build => write!(f, "build"),
+            VariableScope::Runtime => write!(f, "runtime"),
+            VariableScope::Both => write!(f, "both"),
+        }
+    }
+}
+
+impl VariableScope {
+    pub fn is_build(self) -> bool {
+        self != VariableScope::Runtime
+    }
+
+    pub fn is_runtime(self) -> bool {
+        self != VariableScope::Build
+    }
+}
 
 #[derive(Default, Debug)]
 pub struct Environment {
     variables: EnvironmentVariables,
+    scopes: VariableScopes,
 }
 
 impl Environment {
     pub fn new(variables: EnvironmentVariables) -> Environment {
-        Environment { variables }
+        Environment {
+            variables,
+            ..Default::default()
+        }
     }
 
+    /// Parse variables in the form `NAME=value` or `NAME`, optionally prefixed with `build:`, `runtime:` or `both:`
     pub fn from_envs(envs: Vec<&str>) -> Result<Environment> {
         let mut environment = Environment::default();
         for env in envs {
-            let matches = Regex::new(r"([A-Za-z0-9_-]*)(?:=?)([\s\S]*)")
+            let matches = Regex::new(r"^(?:(build|runtime|both):)?([A-Za-z0-9_-]*)(?:=?)([\s\S]*)")
                 .unwrap()
                 .captures(env)
                 .unwrap();
-            if matches.get(2).unwrap().as_str() == "" {
+            let name = matches.get(2).unwrap().as_str();
+            if matches.get(3).unwrap().as_str() == "" {
                 // No value, pull from the current environment
-                let name = matches.get(1).unwrap().as_str();
                 if let Ok(value) = env::var(name) {
                     environment.set_variable(name.to_string(), value);
                 }
             } else {
                 // Use provided name, value pair
                 environment.set_variable(
-                    matches.get(1).unwrap().as_str().to_string(),
-                    matches.get(2).unwrap().as_str().to_string(),
+                    name.to_string(),
+                    matches.get(3).unwrap().as_str().to_string(),
                 );
             }
+
+            let scope = match matches.get(1).map(|m| m.as_str()) {
+                Some("build") => Some(VariableScope::Build),
+                Some("runtime") => Some(VariableScope::Runtime),
+                Some("both") => Some(VariableScope::Both),
+                _ => None,
+            };
+            if let Some(scope) = scope {
+                environment.scopes.insert(name.to_string(), scope);
+            }
         }
 
         Ok(environment)
@@ -67,11 +127,16 @@ impl Environment {
     pub fn clone_variables(env: &Environment) -> EnvironmentVariables {
         env.variables.clone()
     }
+
+    /// The scopes that were given for variables, e.g. with `build:NAME=value`
+    pub fn get_variable_scopes(&self) -> VariableScopes {
+        self.scopes.clone()
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Environment;
+    use super::{Environment, VariableScope, VariableScopes};
 
     #[test]
     fn set_and_get_variables() {
@@ -96,6 +161,25 @@ mod tests {
         assert_eq!(environment.get_variable("INVALID"), Some("ENV=CONFIG"));
     }
 
+    #[test]
+    fn test_environment_variable_scopes() {
+        let environment = Environment::from_envs(vec![
+            "build:NPM_TOKEN=abc",
+            "runtime:PORT=3000",
+            "HELLO=world",
+        ])
+        .unwrap();
+        assert_eq!(environment.get_variable("NPM_TOKEN"), Some("abc"));
+        assert_eq!(environment.get_variable("PORT"), Some("3000"));
+        assert_eq!(
+            environment.get_variable_scopes(),
+            VariableScopes::from([
+                ("NPM_TOKEN".to_string(), VariableScope::Build),
+                ("PORT".to_string(), VariableScope::Runtime),
+            ])
+        );
+    }
+
     #[test]
     fn test_get_config_variable() {
         let mut environment = Environment::default();
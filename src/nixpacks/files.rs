@@ -1,14 +1,96 @@
-use anyhow::Result;
-use ignore::WalkBuilder;
+use anyhow::{Context, Result};
+use ignore::{gitignore::GitignoreBuilder, Walk, WalkBuilder};
 use std::{fs, io, path::Path};
 
-pub fn recursive_copy_dir<T: AsRef<Path>, Q: AsRef<Path>>(source: T, dest: Q) -> Result<()> {
-    let walker = WalkBuilder::new(&source)
+/// The ignore file of the app that uses the `.dockerignore` format
+pub const DOCKER_IGNORE_FILE: &str = ".dockerignore";
+
+/// The ignore file of the app that uses the `.gitignore` format, like the `exclude` of the plan
+pub const NIXPACKS_IGNORE_FILE: &str = ".nixpacksignore";
+
+/// The patterns from the ignore files of the app, followed by the `excludes` of the plan, all
+/// in the `.dockerignore` format
+pub fn get_ignore_patterns<T: AsRef<Path>>(source: T, excludes: &[String]) -> Result<Vec<String>> {
+    let mut patterns = read_ignore_file(source.as_ref(), DOCKER_IGNORE_FILE)?;
+    patterns.extend(
+        read_ignore_file(source.as_ref(), NIXPACKS_IGNORE_FILE)?
+            .iter()
+            .chain(excludes)
+            .map(|pattern| gitignore_to_dockerignore(pattern)),
+    );
+
+    Ok(patterns)
+}
+
+fn read_ignore_file(source: &Path, file: &str) -> Result<Vec<String>> {
+    let path = source.join(file);
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+
+    let contents = fs::read_to_string(&path).context(format!("Reading {file}"))?;
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(ToString::to_string)
+        .collect())
+}
+
+/// `.gitignore` patterns without a slash match at any depth, while `.dockerignore` patterns are
+/// always relative to the root of the context
+fn gitignore_to_dockerignore(pattern: &str) -> String {
+    let (negation, pattern) = match pattern.strip_prefix('!') {
+        Some(pattern) => ("!", pattern),
+        None => ("", pattern),
+    };
+    // `.dockerignore` has no directory only patterns
+    let pattern = pattern.trim_end_matches('/');
+
+    if pattern.contains('/') {
+        format!("{negation}{}", pattern.trim_start_matches('/'))
+    } else {
+        format!("{negation}**/{pattern}")
+    }
+}
+
+/// Walk the directory, leaving out the paths that match any of the `.dockerignore` style `excludes`
+pub fn walk_dir<T: AsRef<Path>>(source: T, excludes: &[String]) -> Result<Walk> {
+    let mut ignore_builder = GitignoreBuilder::new(&source);
+    for pattern in excludes {
+        let (negation, path) = match pattern.strip_prefix('!') {
+            Some(path) => ("!", path),
+            None => ("", pattern.as_str()),
+        };
+        // Anchoring every pattern at the root gives them the meaning they have in `.dockerignore`
+        let path = path.trim_start_matches("./").trim_start_matches('/');
+        ignore_builder
+            .add_line(None, &format!("{negation}/{path}"))
+            .context(format!("Invalid exclude pattern `{pattern}`"))?;
+    }
+    let ignore = ignore_builder.build()?;
+
+    Ok(WalkBuilder::new(&source)
         .follow_links(false)
         // this includes hidden directories & files
         .standard_filters(false)
         .hidden(false)
-        .build();
+        .filter_entry(move |entry| {
+            let is_dir = entry
+                .file_type()
+                .map_or(false, |file_type| file_type.is_dir());
+            !ignore.matched(entry.path(), is_dir).is_ignore()
+        })
+        .build())
+}
+
+/// Copy the directory, leaving out the paths that match any of the `.dockerignore` style `excludes`
+pub fn recursive_copy_dir<T: AsRef<Path>, Q: AsRef<Path>>(
+    source: T,
+    dest: Q,
+    excludes: &[String],
+) -> Result<()> {
+    let walker = walk_dir(&source, excludes)?;
 
     for entry in walker {
         let entry = entry?;
@@ -38,3 +120,57 @@ pub fn recursive_copy_dir<T: AsRef<Path>, Q: AsRef<Path>>(source: T, dest: Q) ->
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_recursive_copy_dir_with_excludes() -> Result<()> {
+        let source = TempDir::new("nixpacks-source")?;
+        let dest = TempDir::new("nixpacks-dest")?;
+
+        fs::create_dir_all(source.path().join("node_modules/dep"))?;
+        fs::create_dir_all(source.path().join("src/node_modules"))?;
+        fs::write(source.path().join("node_modules/dep/index.js"), "")?;
+        fs::write(source.path().join("src/node_modules/index.js"), "")?;
+        fs::write(source.path().join("src/index.js"), "")?;
+        fs::write(source.path().join("src/debug.log"), "")?;
+        fs::write(source.path().join("src/notes.txt"), "")?;
+        fs::write(source.path().join("notes.txt"), "")?;
+        fs::write(
+            source.path().join(".dockerignore"),
+            "# deps\nnode_modules\n*.txt\n",
+        )?;
+        fs::write(source.path().join(".nixpacksignore"), "*.log\n")?;
+
+        let excludes = get_ignore_patterns(source.path(), &["/src/secret.txt".to_string()])?;
+        assert_eq!(
+            excludes,
+            vec!["node_modules", "*.txt", "**/*.log", "src/secret.txt"]
+        );
+
+        recursive_copy_dir(source.path(), dest.path(), &excludes)?;
+        assert!(dest.path().join("src/index.js").is_file());
+        assert!(dest.path().join(".dockerignore").is_file());
+        assert!(!dest.path().join("node_modules").exists());
+        assert!(!dest.path().join("src/debug.log").exists());
+
+        // `.dockerignore` patterns only match from the root of the app
+        assert!(!dest.path().join("notes.txt").exists());
+        assert!(dest.path().join("src/notes.txt").is_file());
+        assert!(dest.path().join("src/node_modules/index.js").is_file());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gitignore_to_dockerignore() {
+        assert_eq!(gitignore_to_dockerignore("*.log"), "**/*.log");
+        assert_eq!(gitignore_to_dockerignore("target/"), "**/target");
+        assert_eq!(gitignore_to_dockerignore("/dist"), "dist");
+        assert_eq!(gitignore_to_dockerignore("docs/*.md"), "docs/*.md");
+        assert_eq!(gitignore_to_dockerignore("!keep.log"), "!**/keep.log");
+    }
+}
@@ -0,0 +1,262 @@
+use super::{
+    explain::{FieldExplanation, PlanExplanation, PlanSource},
+    BuildPlan,
+};
+use colored::Colorize;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+/// A field of the build plan that is different between two plans
+#[serde_with::skip_serializing_none]
+#[derive(Serialize, PartialEq, Eq, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldDiff {
+    /// Path of the field in the plan, e.g. `phases.setup.nixPkgs` or `start.cmd`
+    pub path: String,
+    pub before: Option<Vec<String>>,
+    pub after: Option<Vec<String>>,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+
+    #[serde(skip)]
+    is_list: bool,
+}
+
+/// The structural differences between two build plans
+#[derive(Serialize, PartialEq, Eq, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PlanDiff {
+    pub added_phases: Vec<String>,
+    pub removed_phases: Vec<String>,
+    pub fields: Vec<FieldDiff>,
+}
+
+impl PlanDiff {
+    /// Compare the `before` plan with the `after` plan
+    pub fn new(before: &BuildPlan, after: &BuildPlan) -> Self {
+        let before_phases = phase_names(before);
+        let after_phases = phase_names(after);
+
+        let before_fields = PlanExplanation::from_plan(before, &PlanSource::Default).fields;
+        let after_fields = PlanExplanation::from_plan(after, &PlanSource::Default).fields;
+
+        let paths = before_fields
+            .keys()
+            .chain(after_fields.keys())
+            .collect::<BTreeSet<_>>();
+
+        let fields = paths
+            .into_iter()
+            .filter_map(|path| {
+                let before_values = field_values(&before_fields, path);
+                let after_values = field_values(&after_fields, path);
+                if before_values == after_values {
+                    return None;
+                }
+
+                let is_list = before_fields
+                    .get(path)
+                    .or_else(|| after_fields.get(path))
+                    .map_or(false, FieldExplanation::is_list);
+
+                let before_list = before_values.clone().unwrap_or_default();
+                let after_list = after_values.clone().unwrap_or_default();
+
+                Some(FieldDiff {
+                    path: path.clone(),
+                    added: after_list
+                        .iter()
+                        .filter(|v| !before_list.contains(v))
+                        .cloned()
+                        .collect(),
+                    removed: before_list
+                        .iter()
+                        .filter(|v| !after_list.contains(v))
+                        .cloned()
+                        .collect(),
+                    before: before_values,
+                    after: after_values,
+                    is_list,
+                })
+            })
+            .collect();
+
+        PlanDiff {
+            added_phases: after_phases.difference(&before_phases).cloned().collect(),
+            removed_phases: before_phases.difference(&after_phases).cloned().collect(),
+            fields,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added_phases.is_empty() && self.removed_phases.is_empty() && self.fields.is_empty()
+    }
+
+    /// Human readable output of the diff
+    pub fn to_text(&self) -> String {
+        if self.is_empty() {
+            return "No changes\n".to_string();
+        }
+
+        let mut out = String::new();
+
+        for name in &self.added_phases {
+            writeln!(out, "{}", format!("+ phase {name}").green()).unwrap();
+        }
+        for name in &self.removed_phases {
+            writeln!(out, "{}", format!("- phase {name}").red()).unwrap();
+        }
+
+        for field in &self.fields {
+            writeln!(out, "{}", format!("~ {}", field.path).bold()).unwrap();
+
+            if field.is_list && (!field.added.is_empty() || !field.removed.is_empty()) {
+                for value in &field.removed {
+                    writeln!(out, "    {}", format!("- {value}").red()).unwrap();
+                }
+                for value in &field.added {
+                    writeln!(out, "    {}", format!("+ {value}").green()).unwrap();
+                }
+            } else {
+                // Scalars, and lists that only changed order
+                let before = field.before.as_ref().map(|v| v.join(", "));
+                let after = field.after.as_ref().map(|v| v.join(", "));
+                if let Some(before) = before {
+                    writeln!(out, "    {}", format!("- {before}").red()).unwrap();
+                }
+                if let Some(after) = after {
+                    writeln!(out, "    {}", format!("+ {after}").green()).unwrap();
+                }
+            }
+        }
+
+        out
+    }
+}
+
+fn field_values(fields: &BTreeMap<String, FieldExplanation>, path: &str) -> Option<Vec<String>> {
+    fields
+        .get(path)
+        .map(|field| field.values.iter().map(|v| v.value.clone()).collect())
+}
+
+fn phase_names(plan: &BuildPlan) -> BTreeSet<String> {
+    plan.phases
+        .as_ref()
+        .map(|phases| phases.keys().cloned().collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_plans() {
+        let before = BuildPlan::from_toml(
+            r#"
+            [variables]
+            NODE_ENV = "production"
+
+            [phases.setup]
+            nixPkgs = ["nodejs", "yarn"]
+
+            [phases.build]
+            cmds = ["yarn run build"]
+
+            [start]
+            cmd = "yarn run start"
+            "#,
+        )
+        .unwrap();
+        let after = BuildPlan::from_toml(
+            r#"
+            [variables]
+            NODE_ENV = "development"
+
+            [phases.setup]
+            nixPkgs = ["nodejs", "npm"]
+
+            [phases.install]
+            cmds = ["npm ci"]
+
+            [start]
+            cmd = "yarn run start"
+            "#,
+        )
+        .unwrap();
+
+        let diff = PlanDiff::new(&before, &after);
+        assert_eq!(diff.added_phases, vec!["install".to_string()]);
+        assert_eq!(diff.removed_phases, vec!["build".to_string()]);
+
+        let paths = diff
+            .fields
+            .iter()
+            .map(|f| f.path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec![
+                "phases.build.cmds",
+                "phases.install.cmds",
+                "phases.setup.nixPkgs",
+                "variables.NODE_ENV"
+            ]
+        );
+
+        let nix_pkgs = &diff.fields[2];
+        assert_eq!(nix_pkgs.added, vec!["npm".to_string()]);
+        assert_eq!(nix_pkgs.removed, vec!["yarn".to_string()]);
+    }
+
+    #[test]
+    fn test_diff_phase_condition() {
+        let before = BuildPlan::from_toml(
+            r#"
+            [phases.migrate]
+            cmds = ["npm run migrate"]
+            if = { env = "DATABASE_URL" }
+            "#,
+        )
+        .unwrap();
+        let after = BuildPlan::from_toml(
+            r#"
+            [phases.migrate]
+            cmds = ["npm run migrate"]
+            if = { file = "migrations" }
+            "#,
+        )
+        .unwrap();
+
+        let diff = PlanDiff::new(&before, &after);
+        assert_eq!(diff.fields.len(), 1);
+        assert_eq!(diff.fields[0].path, "phases.migrate.if");
+        assert_eq!(
+            diff.fields[0].before,
+            Some(vec!["env DATABASE_URL".to_string()])
+        );
+        assert_eq!(
+            diff.fields[0].after,
+            Some(vec!["file migrations".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_diff_same_plan() {
+        let plan = BuildPlan::from_toml(
+            r#"
+            [phases.setup]
+            nixPkgs = ["nodejs"]
+            "#,
+        )
+        .unwrap();
+
+        let diff = PlanDiff::new(&plan, &plan);
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_text(), "No changes\n");
+    }
+}
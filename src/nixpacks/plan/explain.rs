@@ -0,0 +1,603 @@
+use super::{
+    merge::Mergeable,
+    utils::{get_removal, REMOVE_CMD_PREFIX, REMOVE_PREFIX},
+    BuildPlan,
+};
+use crate::nixpacks::{
+    app::App,
+    environment::{EnvironmentVariables, VariableScopes},
+};
+use anyhow::Result;
+use colored::Colorize;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Write},
+};
+
+const MAX_VALUE_WIDTH: usize = 60;
+
+/// The layer of configuration that a value in the build plan came from
+#[derive(Serialize, PartialEq, Eq, Clone, Debug)]
+#[serde(tag = "type", content = "name", rename_all = "camelCase")]
+pub enum PlanSource {
+    Provider(String),
+    Procfile,
+    ConfigFile(String),
+    Environment,
+    Cli,
+    Lockfile,
+    Hooks,
+    Default,
+}
+
+impl Display for PlanSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanSource::Provider(name) => write!(f, "provider {name}"),
+            PlanSource::Procfile => write!(f, "Procfile"),
+            PlanSource::ConfigFile(file) => write!(f, "{file}"),
+            PlanSource::Environment => write!(f, "environment"),
+            PlanSource::Cli => write!(f, "CLI"),
+            PlanSource::Lockfile => write!(f, "nixpacks.lock"),
+            PlanSource::Hooks => write!(f, "hooks"),
+            PlanSource::Default => write!(f, "nixpacks default"),
+        }
+    }
+}
+
+#[derive(Serialize, PartialEq, Eq, Clone, Debug)]
+pub struct SourcedValue {
+    pub value: String,
+    pub source: PlanSource,
+}
+
+#[derive(Serialize, PartialEq, Eq, Clone, Debug, Default)]
+pub struct FieldExplanation {
+    pub values: Vec<SourcedValue>,
+    pub replaced: Vec<SourcedValue>,
+
+    #[serde(skip)]
+    is_list: bool,
+}
+
+/// Where every field of a build plan came from and what it replaced.
+///
+/// Fields are keyed by their path in the plan, e.g. `phases.setup.nixPkgs` or `start.cmd`.
+#[derive(Serialize, PartialEq, Eq, Clone, Debug, Default)]
+pub struct PlanExplanation {
+    pub fields: BTreeMap<String, FieldExplanation>,
+}
+
+/// A build plan along with the explanation of where its values came from
+#[derive(Clone, Debug, Default)]
+pub struct SourcedPlan {
+    pub plan: BuildPlan,
+    pub explanation: PlanExplanation,
+}
+
+impl PlanExplanation {
+    /// Attribute every value in `plan` to `source`
+    pub fn from_plan(plan: &BuildPlan, source: &PlanSource) -> Self {
+        let mut explanation = PlanExplanation::default();
+
+        let mut list = |name: String, values: &Option<Vec<String>>| {
+            if let Some(values) = values {
+                explanation.insert(name, values, source, true);
+            }
+        };
+
+        list("providers".to_string(), &plan.providers);
+        list("exclude".to_string(), &plan.exclude);
+
+        for (name, phase) in plan.phases.clone().unwrap_or_default() {
+            list(format!("phases.{name}.dependsOn"), &phase.depends_on);
+            list(format!("phases.{name}.nixPkgs"), &phase.nix_pkgs);
+            list(format!("phases.{name}.nixLibs"), &phase.nix_libs);
+            list(format!("phases.{name}.nixOverlays"), &phase.nix_overlays);
+            list(format!("phases.{name}.aptPkgs"), &phase.apt_pkgs);
+            list(format!("phases.{name}.cmds"), &phase.cmds);
+            list(
+                format!("phases.{name}.onlyIncludeFiles"),
+                &phase.only_include_files,
+            );
+            list(
+                format!("phases.{name}.cacheDirectories"),
+                &phase.cache_directories,
+            );
+            list(format!("phases.{name}.paths"), &phase.paths);
+            list(format!("phases.{name}.secrets"), &phase.secrets);
+        }
+
+        if let Some(start) = &plan.start_phase {
+            list(
+                "start.onlyIncludeFiles".to_string(),
+                &start.only_include_files,
+            );
+            list(
+                "start.writableDirectories".to_string(),
+                &start.writable_directories,
+            );
+            list("start.shell".to_string(), &start.shell);
+            list("start.entrypoint".to_string(), &start.entrypoint);
+            list(
+                "start.ports".to_string(),
+                &start
+                    .ports
+                    .as_ref()
+                    .map(|ports| ports.iter().map(ToString::to_string).collect()),
+            );
+        }
+
+        if let Some(hooks) = &plan.hooks {
+            list("hooks.preInstall".to_string(), &hooks.pre_install);
+            list("hooks.postInstall".to_string(), &hooks.post_install);
+            list("hooks.preBuild".to_string(), &hooks.pre_build);
+            list("hooks.postBuild".to_string(), &hooks.post_build);
+            list("hooks.preStart".to_string(), &hooks.pre_start);
+        }
+
+        let mut scalar = |name: String, value: &Option<String>| {
+            if let Some(value) = value {
+                explanation.insert(name, std::slice::from_ref(value), source, false);
+            }
+        };
+
+        scalar("buildImage".to_string(), &plan.build_image);
+        scalar("profile".to_string(), &plan.profile);
+
+        for (name, phase) in plan.phases.clone().unwrap_or_default() {
+            scalar(
+                format!("phases.{name}.nixpkgsArchive"),
+                &phase.nixpkgs_archive,
+            );
+            scalar(
+                format!("phases.{name}.if"),
+                &phase.condition.as_ref().map(ToString::to_string),
+            );
+            for (var, value) in phase.variables.clone().unwrap_or_default() {
+                scalar(format!("phases.{name}.variables.{var}"), &Some(value));
+            }
+        }
+
+        if let Some(start) = &plan.start_phase {
+            scalar("start.cmd".to_string(), &start.cmd);
+            scalar("start.runImage".to_string(), &start.run_image);
+            scalar("start.user".to_string(), &start.user);
+            scalar("start.init".to_string(), &start.init);
+            scalar(
+                "start.exec".to_string(),
+                &start.exec.map(|exec| exec.to_string()),
+            );
+
+            if let Some(healthcheck) = &start.healthcheck {
+                scalar("start.healthcheck.cmd".to_string(), &healthcheck.cmd);
+                scalar("start.healthcheck.path".to_string(), &healthcheck.path);
+                scalar(
+                    "start.healthcheck.port".to_string(),
+                    &healthcheck.port.map(|port| port.to_string()),
+                );
+                scalar(
+                    "start.healthcheck.interval".to_string(),
+                    &healthcheck.interval,
+                );
+                scalar(
+                    "start.healthcheck.timeout".to_string(),
+                    &healthcheck.timeout,
+                );
+                scalar(
+                    "start.healthcheck.startPeriod".to_string(),
+                    &healthcheck.start_period,
+                );
+                scalar(
+                    "start.healthcheck.retries".to_string(),
+                    &healthcheck.retries.map(|retries| retries.to_string()),
+                );
+            }
+        }
+
+        if let Some(start) = &plan.start_phase {
+            for (name, cmd) in start.processes.clone().unwrap_or_default() {
+                scalar(format!("start.processes.{name}"), &Some(cmd));
+            }
+        }
+
+        for (name, value) in plan.variables.clone().unwrap_or_default() {
+            scalar(format!("variables.{name}"), &Some(value));
+        }
+
+        for (name, scope) in plan.variable_scopes.clone().unwrap_or_default() {
+            scalar(format!("variableScopes.{name}"), &Some(scope.to_string()));
+        }
+
+        for (name, value) in plan.static_assets.clone().unwrap_or_default() {
+            scalar(format!("staticAssets.{name}"), &Some(value));
+        }
+
+        for (name, condition) in plan.skipped_phases.clone().unwrap_or_default() {
+            scalar(format!("skippedPhases.{name}"), &Some(condition));
+        }
+
+        explanation
+    }
+
+    /// Explain `plan`, which was made out of the plans that are explained by `layers`.
+    ///
+    /// The values are taken from `plan` itself, so the explanation can not disagree with it. Each value is
+    /// attributed to the last layer that has it, or else to the defaults, and the values of the layers that
+    /// are no longer in `plan` are listed as replaced.
+    pub fn attribute(plan: &BuildPlan, layers: &[&PlanExplanation]) -> Self {
+        let mut explanation = PlanExplanation::from_plan(plan, &PlanSource::Default);
+
+        for (name, field) in &mut explanation.fields {
+            let layer_fields = layers
+                .iter()
+                .filter_map(|layer| layer.get_field(name))
+                .collect::<Vec<_>>();
+
+            for value in &mut field.values {
+                let layer_value = layer_fields
+                    .iter()
+                    .rev()
+                    .find_map(|f| f.values.iter().find(|v| v.value == value.value));
+                if let Some(layer_value) = layer_value {
+                    value.source = layer_value.source.clone();
+                }
+            }
+
+            let mut replaced = Vec::new();
+            for layer_field in layer_fields {
+                replaced.extend(layer_field.replaced.iter().cloned());
+                replaced.extend(
+                    layer_field
+                        .values
+                        .iter()
+                        .filter(|v| !is_marker(name, &v.value))
+                        .filter(|v| !field.values.iter().any(|value| value.value == v.value))
+                        .cloned(),
+                );
+            }
+            field.replaced = replaced;
+        }
+
+        explanation
+    }
+
+    fn insert(&mut self, name: String, values: &[String], source: &PlanSource, is_list: bool) {
+        self.fields.insert(
+            name,
+            FieldExplanation {
+                values: values
+                    .iter()
+                    .map(|value| SourcedValue {
+                        value: value.clone(),
+                        source: source.clone(),
+                    })
+                    .collect(),
+                replaced: Vec::new(),
+                is_list,
+            },
+        );
+    }
+
+    pub fn get_field(&self, name: &str) -> Option<&FieldExplanation> {
+        self.fields.get(name)
+    }
+
+    /// Human readable output of the explanation
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+
+        for (name, field) in &self.fields {
+            if field.values.is_empty() && field.replaced.is_empty() {
+                continue;
+            }
+
+            writeln!(out, "{}", name.bold()).unwrap();
+            for v in &field.values {
+                writeln!(
+                    out,
+                    "  {}  {}",
+                    format_value(&v.value),
+                    format!("({})", v.source).cyan()
+                )
+                .unwrap();
+            }
+            for v in &field.replaced {
+                writeln!(
+                    out,
+                    "  {}  {}",
+                    format_value(&v.value).strikethrough().dimmed(),
+                    format!("(replaced, from {})", v.source).dimmed()
+                )
+                .unwrap();
+            }
+        }
+
+        out
+    }
+}
+
+/// Whether the value of the field marks how a list is merged instead of being a value of it, e.g. `"..."` or `"!value"`
+fn is_marker(name: &str, value: &str) -> bool {
+    let prefix = if name.rsplit('.').next() == Some("cmds") {
+        REMOVE_CMD_PREFIX
+    } else {
+        REMOVE_PREFIX
+    };
+    value == "..." || value == "@auto" || get_removal(value, prefix).is_some()
+}
+
+fn format_value(value: &str) -> String {
+    let first_line = value.lines().next().unwrap_or_default();
+    if first_line.len() > MAX_VALUE_WIDTH || first_line.len() < value.trim_end().len() {
+        let truncated = first_line.chars().take(MAX_VALUE_WIDTH).collect::<String>();
+        format!("{truncated}...")
+    } else {
+        value.to_string()
+    }
+}
+
+impl FieldExplanation {
+    pub fn is_list(&self) -> bool {
+        self.is_list
+    }
+}
+
+impl SourcedPlan {
+    pub fn new(plan: BuildPlan, source: &PlanSource) -> Self {
+        let explanation = PlanExplanation::from_plan(&plan, source);
+        SourcedPlan { plan, explanation }
+    }
+
+    pub fn add_variables(&mut self, variables: EnvironmentVariables, source: &PlanSource) {
+        let variables_plan = BuildPlan {
+            variables: Some(variables.clone()),
+            ..Default::default()
+        };
+        let variables_explanation = PlanExplanation::from_plan(&variables_plan, source);
+
+        self.plan.add_variables(variables);
+        self.explain(&[&variables_explanation]);
+    }
+
+    pub fn add_variable_scopes(&mut self, scopes: VariableScopes, source: &PlanSource) {
+        let scopes_plan = BuildPlan {
+            variable_scopes: Some(scopes.clone()),
+            ..Default::default()
+        };
+        let scopes_explanation = PlanExplanation::from_plan(&scopes_plan, source);
+
+        self.plan.add_variable_scopes(scopes);
+        self.explain(&[&scopes_explanation]);
+    }
+
+    pub fn remove_skipped_phases(&mut self, app: &App) -> Result<()> {
+        self.plan.remove_skipped_phases(app)?;
+        self.explain(&[]);
+        Ok(())
+    }
+
+    pub fn pin(&mut self) {
+        self.plan.pin();
+        self.explain(&[]);
+    }
+
+    /// Explain the plan again after it was changed with the values of `layers`
+    fn explain(&mut self, layers: &[&PlanExplanation]) {
+        let previous = std::mem::take(&mut self.explanation);
+        let layers = [&[&previous], layers].concat();
+        self.explanation = PlanExplanation::attribute(&self.plan, &layers);
+    }
+
+    pub fn merge_plans(plans: &[SourcedPlan]) -> SourcedPlan {
+        plans.iter().fold(SourcedPlan::default(), |acc, plan| {
+            SourcedPlan::merge(&acc, plan)
+        })
+    }
+}
+
+impl Mergeable for SourcedPlan {
+    fn merge(c1: &SourcedPlan, c2: &SourcedPlan) -> SourcedPlan {
+        let plan = BuildPlan::merge(&c1.plan, &c2.plan);
+        let explanation = PlanExplanation::attribute(&plan, &[&c1.explanation, &c2.explanation]);
+        SourcedPlan { plan, explanation }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        explain_build_plan, generate_build_plan,
+        nixpacks::plan::{generator::GeneratePlanOptions, utils::remove_autos_from_vec},
+    };
+    use std::fs;
+
+    /// Values of a field without their sources, with the `"..."` markers removed
+    fn field_values(explanation: &PlanExplanation, name: &str) -> Vec<String> {
+        remove_autos_from_vec(
+            explanation
+                .get_field(name)
+                .map(|f| f.values.iter().map(|v| v.value.clone()).collect())
+                .unwrap_or_default(),
+        )
+    }
+
+    fn sourced(toml: &str, source: &PlanSource) -> SourcedPlan {
+        SourcedPlan::new(BuildPlan::from_toml(toml).unwrap(), source)
+    }
+
+    #[test]
+    fn test_tracks_sources_of_extended_lists() {
+        let merged = SourcedPlan::merge_plans(&[
+            sourced(
+                r#"
+                [phases.setup]
+                nixPkgs = ["nodejs", "yarn"]
+                "#,
+                &PlanSource::Provider("node".to_string()),
+            ),
+            sourced(
+                r#"
+                [phases.setup]
+                nixPkgs = ["...", "cowsay"]
+                "#,
+                &PlanSource::ConfigFile("nixpacks.toml".to_string()),
+            ),
+        ]);
+
+        let field = merged
+            .explanation
+            .get_field("phases.setup.nixPkgs")
+            .unwrap();
+        let sources = field
+            .values
+            .iter()
+            .filter(|v| v.value != "...")
+            .map(|v| (v.value.as_str(), v.source.to_string()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            sources,
+            vec![
+                ("nodejs", "provider node".to_string()),
+                ("yarn", "provider node".to_string()),
+                ("cowsay", "nixpacks.toml".to_string()),
+            ]
+        );
+        assert!(field.replaced.is_empty());
+    }
+
+    #[test]
+    fn test_tracks_replaced_values() {
+        let merged = SourcedPlan::merge_plans(&[
+            sourced(
+                r#"
+                [start]
+                cmd = "npm run start"
+                "#,
+                &PlanSource::Provider("node".to_string()),
+            ),
+            sourced(
+                r#"
+                [start]
+                cmd = "node server.js"
+                "#,
+                &PlanSource::Cli,
+            ),
+        ]);
+
+        let field = merged.explanation.get_field("start.cmd").unwrap();
+        assert_eq!(field.values[0].value, "node server.js");
+        assert_eq!(field.values[0].source, PlanSource::Cli);
+        assert_eq!(field.replaced[0].value, "npm run start");
+        assert_eq!(
+            field.replaced[0].source,
+            PlanSource::Provider("node".to_string())
+        );
+    }
+
+    #[test]
+    fn test_tracks_sources_of_phase_conditions() {
+        let merged = SourcedPlan::merge_plans(&[
+            sourced(
+                r#"
+                [phases.migrate]
+                cmds = ["npm run migrate"]
+                if = { env = "DATABASE_URL" }
+                "#,
+                &PlanSource::Provider("node".to_string()),
+            ),
+            sourced(
+                r#"
+                [phases.migrate]
+                if = { file = "migrations" }
+                "#,
+                &PlanSource::ConfigFile("nixpacks.toml".to_string()),
+            ),
+        ]);
+
+        let field = merged.explanation.get_field("phases.migrate.if").unwrap();
+        assert_eq!(field.values[0].value, "file migrations");
+        assert_eq!(
+            field.values[0].source,
+            PlanSource::ConfigFile("nixpacks.toml".to_string())
+        );
+        assert_eq!(field.replaced[0].value, "env DATABASE_URL");
+    }
+
+    #[test]
+    fn test_explanation_matches_merged_plan() {
+        let mut merged = SourcedPlan::merge_plans(&[
+            sourced(
+                r#"
+                [phases.setup]
+                nixPkgs = ["python3"]
+                "#,
+                &PlanSource::Provider("python".to_string()),
+            ),
+            sourced(
+                r#"
+                [phases.install]
+                cmds = ["pip install -r requirements.txt"]
+                "#,
+                &PlanSource::Environment,
+            ),
+        ]);
+        merged.pin();
+
+        let install = merged.plan.get_phase("install").unwrap();
+        assert_eq!(
+            field_values(&merged.explanation, "phases.install.dependsOn"),
+            install.depends_on.clone().unwrap()
+        );
+        assert_eq!(
+            merged
+                .explanation
+                .get_field("phases.setup.nixpkgsArchive")
+                .unwrap()
+                .values[0]
+                .source,
+            PlanSource::Default
+        );
+    }
+
+    #[test]
+    fn test_explanation_matches_plan_of_every_example() -> Result<()> {
+        let options = GeneratePlanOptions::default();
+
+        for entry in fs::read_dir("examples")? {
+            let path = entry?.path();
+            let path = path.to_str().unwrap();
+
+            let plan = match generate_build_plan(path, Vec::new(), &options) {
+                Ok(plan) => plan,
+                Err(_) => continue,
+            };
+            let explanation = explain_build_plan(path, Vec::new(), &options)?;
+
+            let plan_fields = PlanExplanation::from_plan(&plan, &PlanSource::Default).fields;
+            assert_eq!(
+                plan_fields.keys().collect::<Vec<_>>(),
+                explanation.fields.keys().collect::<Vec<_>>(),
+                "fields of {path}"
+            );
+            for (name, field) in plan_fields {
+                let values = field
+                    .values
+                    .into_iter()
+                    .map(|v| v.value)
+                    .collect::<Vec<_>>();
+                let explained = explanation.fields[&name]
+                    .values
+                    .iter()
+                    .map(|v| v.value.clone())
+                    .collect::<Vec<_>>();
+                assert_eq!(values, explained, "{name} of {path}");
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,18 +1,25 @@
-use std::path::Path;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
 use crate::{
     nixpacks::{
         app::App,
-        environment::{Environment, EnvironmentVariables},
+        environment::{Environment, EnvironmentVariables, VariableScope},
         plan::{BuildPlan, PlanGenerator},
     },
     providers::{procfile::ProcfileProvider, Provider},
 };
 use anyhow::{bail, Context, Ok, Result};
 use colored::Colorize;
+use path_slash::PathExt;
 
 use super::{
+    explain::{PlanExplanation, PlanSource, SourcedPlan},
+    lock::Lockfile,
     merge::Mergeable,
+    schema::{find_unknown_keys, validate_config, ConfigFormat},
     utils::{fill_auto_in_vec, remove_autos_from_vec},
 };
 
@@ -22,6 +29,10 @@ const NIXPACKS_METADATA: &str = "NIXPACKS_METADATA";
 pub struct GeneratePlanOptions {
     pub plan: Option<BuildPlan>,
     pub config_file: Option<String>,
+    pub strict: bool,
+    pub profile: Option<String>,
+    /// Ignore the existing lock file so that a new one can be created
+    pub update_lock: bool,
 }
 
 pub struct NixpacksBuildPlanGenerator<'a> {
@@ -34,12 +45,12 @@ impl<'a> PlanGenerator for NixpacksBuildPlanGenerator<'a> {
         // If the provider defines a build plan in the new format, use that
         let plan = self.get_build_plan(app, environment)?;
 
-        Ok(plan)
+        Ok(plan.plan)
     }
 
     fn get_plan_providers(&self, app: &App, env: &Environment) -> Result<Vec<String>> {
         let plan_before_providers = self.get_plan_before_providers(app, env)?;
-        let providers = self.get_all_providers(app, env, plan_before_providers.providers)?;
+        let providers = self.get_all_providers(app, env, plan_before_providers.plan.providers)?;
 
         Ok(providers)
     }
@@ -53,34 +64,160 @@ impl NixpacksBuildPlanGenerator<'_> {
         NixpacksBuildPlanGenerator { providers, config }
     }
 
-    /// Get a build plan from the provider and by applying the config from the environment
-    fn get_build_plan(&self, app: &App, env: &Environment) -> Result<BuildPlan> {
+    /// Explain where every value of the generated build plan came from
+    pub fn explain_plan(&self, app: &App, env: &Environment) -> Result<PlanExplanation> {
+        let plan = self.get_build_plan(app, env)?;
+
+        Ok(plan.explanation)
+    }
+
+    /// Create a lock file from the plan that is generated without an existing lock file
+    pub fn get_lockfile(&self, app: &App, env: &Environment) -> Result<Lockfile> {
+        let plan_before_providers = self.get_plan_before_providers(app, env)?;
+        let providers =
+            self.get_all_providers(app, env, plan_before_providers.plan.providers.clone())?;
+
+        let plan = self.get_merged_plan(app, env, plan_before_providers, &providers)?;
+        let plan = self.finish_build_plan(app, env, plan)?;
+
+        Ok(Lockfile::new(&plan.plan, providers))
+    }
+
+    /// Get a build plan and pin it to the values in the lock file, if there is one.
+    ///
+    /// The providers are only run once. The plan without the lock file is what a new lock file would contain,
+    /// so it is used to check whether the existing lock file is out of date.
+    fn get_build_plan(&self, app: &App, env: &Environment) -> Result<SourcedPlan> {
+        let lockfile = if self.config.update_lock {
+            None
+        } else {
+            Lockfile::read(&app.source)?
+        };
+
         let plan_before_providers = self.get_plan_before_providers(app, env)?;
+        let providers =
+            self.get_all_providers(app, env, plan_before_providers.plan.providers.clone())?;
+
+        let lockfile = if let Some(lockfile) = lockfile {
+            lockfile
+        } else {
+            let plan = self.get_merged_plan(app, env, plan_before_providers, &providers)?;
+            return self.finish_build_plan(app, env, plan);
+        };
+
+        // The locked providers are used even when different ones are detected now
+        let plan = if lockfile.providers == providers {
+            let plan = self.get_merged_plan(app, env, plan_before_providers, &providers)?;
+            let unlocked_plan = self.finish_build_plan(app, env, plan.clone())?;
+            if lockfile.is_outdated(&Lockfile::new(&unlocked_plan.plan, providers)) {
+                print_outdated_lockfile_warning();
+            }
+            plan
+        } else {
+            print_outdated_lockfile_warning();
+            self.get_merged_plan(app, env, plan_before_providers, &lockfile.providers)?
+        };
+
+        let locked_plan = SourcedPlan::new(lockfile.to_plan(&plan.plan), &PlanSource::Lockfile);
+        self.finish_build_plan(app, env, SourcedPlan::merge(&plan, &locked_plan))
+    }
+
+    /// Merge the plans of the `providers` and the Procfile beneath the plan from the config
+    fn get_merged_plan(
+        &self,
+        app: &App,
+        env: &Environment,
+        plan_before_providers: SourcedPlan,
+        providers: &[String],
+    ) -> Result<SourcedPlan> {
+        let provider_plan = self.get_plan_from_providers(app, env, providers)?;
+
+        let procfile_plan = SourcedPlan::new(
+            (ProcfileProvider {})
+                .get_build_plan(app, env)?
+                .unwrap_or_default(),
+            &PlanSource::Procfile,
+        );
 
-        let provider_plan =
-            self.get_plan_from_providers(app, env, plan_before_providers.providers.clone())?;
+        Ok(SourcedPlan::merge_plans(&[
+            provider_plan,
+            procfile_plan,
+            plan_before_providers,
+        ]))
+    }
 
-        let procfile_plan = (ProcfileProvider {})
-            .get_build_plan(app, env)?
-            .unwrap_or_default();
+    /// Apply the hooks, init and variables to a merged plan, then pin and validate it
+    fn finish_build_plan(
+        &self,
+        app: &App,
+        env: &Environment,
+        mut plan: SourcedPlan,
+    ) -> Result<SourcedPlan> {
+        // Hooks are turned into phases once the phases of all the providers are known
+        if plan.plan.hooks.is_some() {
+            let hooks_plan = SourcedPlan::new(plan.plan.get_hooks_plan(), &PlanSource::Hooks);
+            plan = SourcedPlan::merge(&plan, &hooks_plan);
+            plan.plan.hooks = None;
+        }
 
-        let mut plan =
-            BuildPlan::merge_plans(&vec![provider_plan, procfile_plan, plan_before_providers]);
+        if plan
+            .plan
+            .start_phase
+            .as_ref()
+            .map_or(false, |start| start.init.is_some())
+        {
+            let init_plan = SourcedPlan::new(plan.plan.get_init_plan(), &PlanSource::Default);
+            plan = SourcedPlan::merge(&plan, &init_plan);
+        }
 
         if !env.get_variable_names().is_empty() {
-            plan.add_variables(Environment::clone_variables(env));
+            plan.add_variables(Environment::clone_variables(env), &PlanSource::Environment);
+        }
+
+        let env_scopes = env.get_variable_scopes();
+        if !env_scopes.is_empty() {
+            plan.add_variable_scopes(env_scopes, &PlanSource::Environment);
+        }
+
+        // Apps can always listen on `PORT`, even when the platform does not set it
+        let port_variables = plan.plan.get_port_variables();
+        if !port_variables.is_empty() {
+            plan.add_variable_scopes(
+                port_variables
+                    .keys()
+                    .map(|name| (name.clone(), VariableScope::Runtime))
+                    .collect(),
+                &PlanSource::Default,
+            );
+            plan.add_variables(port_variables, &PlanSource::Default);
+        }
+
+        plan.remove_skipped_phases(app)?;
+
+        if self.is_strict(env) {
+            let undefined = plan.plan.get_undefined_variables();
+            if !undefined.is_empty() {
+                bail!(
+                    "Undefined variables referenced in the build plan: {}",
+                    undefined.join(", ")
+                );
+            }
         }
 
         plan.pin();
+        plan.plan.validate()?;
 
         Ok(plan)
     }
 
-    fn get_plan_before_providers(&self, app: &App, env: &Environment) -> Result<BuildPlan> {
+    fn get_plan_before_providers(&self, app: &App, env: &Environment) -> Result<SourcedPlan> {
         let file_plan = self.read_file_plan(app, env)?;
-        let env_plan = BuildPlan::from_environment(env);
-        let cli_plan = self.config.plan.clone().unwrap_or_default();
-        let plan_before_providers = BuildPlan::merge_plans(&vec![file_plan, env_plan, cli_plan]);
+        let env_plan = SourcedPlan::new(BuildPlan::from_environment(env), &PlanSource::Environment);
+        let cli_plan = SourcedPlan::new(
+            self.config.plan.clone().unwrap_or_default(),
+            &PlanSource::Cli,
+        );
+        let plan_before_providers = SourcedPlan::merge_plans(&[file_plan, env_plan, cli_plan]);
 
         Ok(plan_before_providers)
     }
@@ -123,10 +260,8 @@ impl NixpacksBuildPlanGenerator<'_> {
         &self,
         app: &App,
         env: &Environment,
-        manual_providers: Option<Vec<String>>,
-    ) -> Result<BuildPlan> {
-        let provider_names = self.get_all_providers(app, env, manual_providers)?;
-
+        provider_names: &[String],
+    ) -> Result<SourcedPlan> {
         if provider_names.len() > 1 {
             println!(
                 "{}",
@@ -134,13 +269,13 @@ impl NixpacksBuildPlanGenerator<'_> {
             );
         }
 
-        let mut plan = BuildPlan::default();
+        let mut plan = SourcedPlan::default();
         let mut count = 0;
 
         let mut metadata = Vec::new();
 
         for name in provider_names {
-            let provider = self.providers.iter().find(|p| p.name() == name);
+            let provider = self.providers.iter().find(|p| p.name() == name.as_str());
             if let Some(provider) = provider {
                 if let Some(mut provider_plan) = provider.get_build_plan(app, env)? {
                     // All but the first provider have their phases prefixed with their name
@@ -153,7 +288,11 @@ impl NixpacksBuildPlanGenerator<'_> {
                         .join_as_comma_separated(provider.name().to_owned());
                     metadata.push(metadata_string);
 
-                    plan = BuildPlan::merge(&provider_plan, &plan);
+                    let provider_plan = SourcedPlan::new(
+                        provider_plan,
+                        &PlanSource::Provider(provider.name().to_string()),
+                    );
+                    plan = SourcedPlan::merge(&provider_plan, &plan);
                 }
             } else if name != "..." && name != "@auto" {
                 bail!("Provider {} not found", name);
@@ -163,16 +302,151 @@ impl NixpacksBuildPlanGenerator<'_> {
         }
 
         if count > 0 {
-            plan.add_variables(EnvironmentVariables::from([(
-                NIXPACKS_METADATA.to_string(),
-                metadata.join(","),
-            )]));
+            plan.add_variables(
+                EnvironmentVariables::from([(NIXPACKS_METADATA.to_string(), metadata.join(","))]),
+                &PlanSource::Default,
+            );
         }
 
         Ok(plan)
     }
 
-    fn read_file_plan(&self, app: &App, env: &Environment) -> Result<BuildPlan> {
+    /// Whether config problems should fail the build instead of only warning
+    fn is_strict(&self, env: &Environment) -> bool {
+        self.config.strict || env.is_config_variable_truthy("STRICT")
+    }
+
+    /// Read a config file and merge it on top of all the files it `extends`
+    fn read_config_file(
+        &self,
+        app: &App,
+        env: &Environment,
+        path: &Path,
+        visited: &mut Vec<(PathBuf, String)>,
+    ) -> Result<SourcedPlan> {
+        let canonical_path = path.canonicalize().with_context(|| {
+            format!(
+                "Nixpacks config file `{}` does not exist",
+                app.strip_source_path(path)
+                    .unwrap_or_default()
+                    .to_slash_lossy()
+            )
+        })?;
+        let file_name = app
+            .strip_source_path(&canonical_path)?
+            .to_slash_lossy()
+            .to_string();
+        if let Some(start) = visited.iter().position(|(p, _)| p == &canonical_path) {
+            let chain = visited[start..]
+                .iter()
+                .map(|(_, name)| name.as_str())
+                .chain([file_name.as_str()])
+                .collect::<Vec<_>>();
+            bail!(
+                "Nixpacks config files extend each other in a cycle: {}",
+                chain.join(" -> ")
+            );
+        }
+
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read Nixpacks config file `{file_name}`"))?
+            .replace("\r\n", "\n");
+
+        let ext = path.extension().unwrap_or_default();
+        let format = if ext == "toml" {
+            ConfigFormat::Toml
+        } else if ext == "json" {
+            ConfigFormat::Json
+        } else {
+            bail!("Unknown file type: {file_name}")
+        };
+
+        let mut plan = match format {
+            ConfigFormat::Toml => BuildPlan::from_toml(&contents),
+            ConfigFormat::Json => BuildPlan::from_json(&contents),
+        }
+        .with_context(|| format!("Failed to parse Nixpacks config file `{file_name}`"))?;
+
+        // These are only set by Nixpacks while generating the plan
+        for (key, is_set) in [
+            ("profile", plan.profile.take().is_some()),
+            ("skippedPhases", plan.skipped_phases.take().is_some()),
+        ] {
+            if is_set {
+                println!(
+                    "{}",
+                    format!("Warning: `{key}` is set by Nixpacks and is ignored in `{file_name}`")
+                        .bright_yellow()
+                );
+            }
+        }
+
+        if self.is_strict(env) {
+            validate_config(&contents, &file_name, format)?;
+        } else {
+            for key in find_unknown_keys(&contents, &file_name, format)? {
+                println!("{}", format!("Warning: {key}").bright_yellow());
+            }
+        }
+
+        // Base plans are merged beneath this one, in the order they are listed
+        visited.push((canonical_path, file_name.clone()));
+        let mut plans = Vec::new();
+        for base in plan.extends.take().unwrap_or_default() {
+            let base_path = path.parent().unwrap_or(path).join(&base);
+            let base_plan = self
+                .read_config_file(app, env, &base_path, visited)
+                .with_context(|| format!("Failed to extend `{base}` from `{file_name}`"))?;
+            plans.push(base_plan);
+        }
+        visited.pop();
+
+        plans.push(self.apply_profile(plan, &file_name, env)?);
+
+        Ok(SourcedPlan::merge_plans(&plans))
+    }
+
+    /// The profile that selects which `[env.<profile>]` overlays are applied
+    fn get_profile(&self, env: &Environment) -> Option<String> {
+        self.config
+            .profile
+            .clone()
+            .or_else(|| env.get_config_variable("PROFILE"))
+    }
+
+    /// Merge the overlay for the selected profile on top of a plan read from `file_name`
+    fn apply_profile(
+        &self,
+        mut plan: BuildPlan,
+        file_name: &str,
+        env: &Environment,
+    ) -> Result<SourcedPlan> {
+        let profiles = plan.profiles.take().unwrap_or_default();
+        let file_plan = SourcedPlan::new(plan, &PlanSource::ConfigFile(file_name.to_string()));
+
+        let profile = match self.get_profile(env) {
+            Some(profile) => profile,
+            None => return Ok(file_plan),
+        };
+        let mut overlay = match profiles.get(&profile) {
+            Some(overlay) => overlay.clone(),
+            None => return Ok(file_plan),
+        };
+
+        if overlay.extends.is_some() || overlay.profiles.is_some() {
+            bail!("`extends` and `env` cannot be used inside `env.{profile}` in `{file_name}`");
+        }
+
+        overlay.profile = Some(profile.clone());
+        let overlay = SourcedPlan::new(
+            overlay,
+            &PlanSource::ConfigFile(format!("{file_name} env.{profile}")),
+        );
+
+        Ok(SourcedPlan::merge(&file_plan, &overlay))
+    }
+
+    fn read_file_plan(&self, app: &App, env: &Environment) -> Result<SourcedPlan> {
         let file_path = if let Some(file_path) = &self.config.config_file {
             Some(file_path.clone())
         } else if let Some(env_config_file) = env.get_config_variable("CONFIG_FILE") {
@@ -189,37 +463,39 @@ impl NixpacksBuildPlanGenerator<'_> {
             None
         };
 
-        let plan =
-            if let Some(file_path) = file_path {
-                let filename = Path::new(&file_path);
-                let ext = filename.extension().unwrap_or_default();
-
-                let contents = app.read_file(file_path.as_str()).with_context(|| {
-                    format!("Failed to read Nixpacks config file `{}`", file_path)
-                })?;
-                let plan = if ext == "toml" {
-                    BuildPlan::from_toml(&contents)
-                } else if ext == "json" {
-                    BuildPlan::from_json(&contents)
-                } else {
-                    bail!("Unknown file type: {}", file_path)
-                };
-
-                Some(plan.with_context(|| {
-                    format!("Failed to parse Nixpacks config file `{}`", file_path)
-                })?)
-            } else {
-                None
-            };
-
-        if plan.is_some() {
+        let plan = if let Some(file_path) = file_path {
+            let path = app.source.join(&file_path);
+            Some(self.read_config_file(app, env, &path, &mut Vec::new())?)
+        } else {
+            None
+        };
+
+        if let Some(plan) = &plan {
             println!(
                 "{}",
                 "\n Nixpacks file based configuration is experimental and may change\n"
                     .bright_yellow()
             );
+
+            if let Some(profile) = self.get_profile(env) {
+                if plan.plan.profile.is_none() {
+                    println!(
+                        "{}",
+                        format!("Warning: No `[env.{profile}]` overlay found in the Nixpacks config file")
+                            .bright_yellow()
+                    );
+                }
+            }
         }
 
         Ok(plan.unwrap_or_default())
     }
 }
+
+fn print_outdated_lockfile_warning() {
+    println!(
+        "{}",
+        "Warning: nixpacks.lock is out of date. Run `nixpacks lock` or pass `--update-lock` to update it"
+            .bright_yellow()
+    );
+}
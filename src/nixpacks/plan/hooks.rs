@@ -0,0 +1,195 @@
+use super::{
+    phase::{Phase, StartPhase},
+    utils::remove_autos_from_vec,
+    BuildPlan,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The order that the phases of a provider run in
+const PROVIDER_PHASES: &[&str] = &["setup", "install", "build"];
+
+/// Commands to run at fixed points of the build, without having to know the phases of the providers
+#[serde_with::skip_serializing_none]
+#[derive(PartialEq, Eq, Serialize, Deserialize, Default, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Hooks {
+    /// Run after the setup phases and before the install phases
+    pub pre_install: Option<Vec<String>>,
+
+    /// Run after the install phases
+    pub post_install: Option<Vec<String>>,
+
+    /// Run before the build phases
+    pub pre_build: Option<Vec<String>>,
+
+    /// Run after the build phases
+    pub post_build: Option<Vec<String>>,
+
+    /// Run when the container starts, before the start command
+    pub pre_start: Option<Vec<String>>,
+}
+
+impl Hooks {
+    /// The hooks that run as phases, with the index of the first provider phase that runs after them
+    fn get_phase_hooks(&self) -> Vec<(&'static str, usize, Vec<String>)> {
+        [
+            ("preInstall", 1, &self.pre_install),
+            ("postInstall", 2, &self.post_install),
+            ("preBuild", 2, &self.pre_build),
+            ("postBuild", PROVIDER_PHASES.len(), &self.post_build),
+        ]
+        .into_iter()
+        .filter_map(|(name, position, cmds)| {
+            let cmds = remove_autos_from_vec(cmds.clone().unwrap_or_default());
+            if cmds.is_empty() {
+                None
+            } else {
+                Some((name, position, cmds))
+            }
+        })
+        .collect()
+    }
+}
+
+impl BuildPlan {
+    /// A plan that, when merged on top of this one, adds a phase for each hook and the
+    /// dependencies that run it between the right phases of every provider.
+    ///
+    /// Providers are told apart by the prefix of their phases, e.g. `python:install`.
+    #[must_use]
+    pub fn get_hooks_plan(&self) -> BuildPlan {
+        let hooks = self.hooks.clone().unwrap_or_default();
+        let mut hooks_plan = BuildPlan::default();
+
+        // Provider phases grouped by their prefix, with their position in PROVIDER_PHASES
+        let mut providers: BTreeMap<String, Vec<(usize, String)>> = BTreeMap::new();
+        for name in self.phases.clone().unwrap_or_default().into_keys() {
+            let (prefix, phase_name) = match name.rsplit_once(':') {
+                Some((prefix, phase_name)) => (prefix.to_string(), phase_name.to_string()),
+                None => (String::new(), name.clone()),
+            };
+            if let Some(position) = PROVIDER_PHASES.iter().position(|p| *p == phase_name) {
+                providers.entry(prefix).or_default().push((position, name));
+            }
+        }
+
+        let mut previous_hooks: Vec<String> = Vec::new();
+        for (hook_name, position, cmds) in hooks.get_phase_hooks() {
+            let mut hook = Phase::new(hook_name);
+            hook.cmds = Some(cmds);
+
+            for phases in providers.values() {
+                // The last phase of the provider that runs before the hook
+                if let Some((_, name)) = phases.iter().filter(|(p, _)| *p < position).max() {
+                    hook.depends_on_phase(name);
+                }
+
+                // The first phase of the provider that runs after the hook
+                if let Some((_, name)) = phases.iter().filter(|(p, _)| *p >= position).min() {
+                    add_dependency(&mut hooks_plan, name, hook_name);
+                }
+            }
+
+            for previous in &previous_hooks {
+                hook.depends_on_phase(previous);
+            }
+            previous_hooks = vec![hook_name.to_string()];
+
+            hooks_plan.add_phase(hook);
+        }
+
+        if let Some(pre_start) = hooks.pre_start {
+            let pre_start = remove_autos_from_vec(pre_start);
+            if !pre_start.is_empty() {
+                let prepend = |cmd: &String| format!("{} && {}", pre_start.join(" && "), cmd);
+                let start = self.start_phase.clone().unwrap_or_default();
+                hooks_plan.start_phase = Some(StartPhase {
+                    cmd: start.cmd.as_ref().map(prepend),
+                    processes: start.processes.as_ref().map(|processes| {
+                        processes
+                            .iter()
+                            .map(|(name, cmd)| (name.clone(), prepend(cmd)))
+                            .collect()
+                    }),
+                    ..Default::default()
+                });
+            }
+        }
+
+        hooks_plan
+    }
+}
+
+/// Make a phase of the hooks plan depend on `dependency` as well as the dependencies it already has
+fn add_dependency(plan: &mut BuildPlan, name: &str, dependency: &str) {
+    match plan.get_phase_mut(name) {
+        Some(phase) => phase.depends_on_phase(dependency),
+        None => plan.add_phase(Phase {
+            name: Some(name.to_string()),
+            depends_on: Some(vec!["...".to_string(), dependency.to_string()]),
+            ..Default::default()
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nixpacks::plan::merge::Mergeable;
+
+    #[test]
+    fn test_hooks_plan() {
+        let plan = BuildPlan::from_toml(
+            r#"
+            [phases.setup]
+            [phases.install]
+            dependsOn = ["setup"]
+            [phases.build]
+            dependsOn = ["install"]
+            [phases."python:setup"]
+            [phases."python:build"]
+            dependsOn = ["python:setup"]
+
+            [hooks]
+            preInstall = ["echo pre install"]
+            postInstall = ["echo post install"]
+            preBuild = ["echo pre build"]
+            preStart = ["./migrate"]
+
+            [start]
+            cmd = "npm run start"
+            "#,
+        )
+        .unwrap();
+
+        let mut merged = BuildPlan::merge(&plan, &plan.get_hooks_plan());
+        merged.pin();
+        let depends_on = |name: &str| merged.get_phase(name).unwrap().depends_on.clone().unwrap();
+
+        assert_eq!(depends_on("preInstall"), vec!["setup", "python:setup"]);
+        assert_eq!(depends_on("install"), vec!["setup", "preInstall"]);
+        assert_eq!(
+            depends_on("python:build"),
+            vec!["python:setup", "preInstall", "postInstall", "preBuild"]
+        );
+        assert_eq!(
+            depends_on("postInstall"),
+            vec!["install", "python:setup", "preInstall"]
+        );
+        assert_eq!(
+            depends_on("preBuild"),
+            vec!["install", "python:setup", "postInstall"]
+        );
+        assert_eq!(
+            depends_on("build"),
+            vec!["install", "postInstall", "preBuild"]
+        );
+        assert!(merged.validate().is_ok());
+        assert_eq!(
+            merged.start_phase.unwrap().cmd,
+            Some("./migrate && npm run start".to_string())
+        );
+    }
+}
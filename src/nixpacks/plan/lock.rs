@@ -0,0 +1,260 @@
+use super::{phase::Phase, BuildPlan};
+use crate::nixpacks::NIX_PACKS_VERSION;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+pub const LOCKFILE_NAME: &str = "nixpacks.lock";
+const LOCKFILE_VERSION: u32 = 1;
+const LOCKFILE_HEADER: &str =
+    "# This file is generated by `nixpacks lock`. It is not intended for manual editing.\n";
+
+/// The resolved values of a phase that are pinned by the lock file
+#[serde_with::skip_serializing_none]
+#[derive(PartialEq, Eq, Default, Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LockedPhase {
+    pub nixpkgs_archive: Option<String>,
+    pub nix_pkgs: Option<Vec<String>>,
+    pub nix_libs: Option<Vec<String>>,
+    pub nix_overlays: Option<Vec<String>>,
+    pub apt_pkgs: Option<Vec<String>>,
+}
+
+/// Everything that needs to stay the same for an app to always produce the same build plan
+#[serde_with::skip_serializing_none]
+#[derive(PartialEq, Eq, Default, Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Lockfile {
+    pub version: u32,
+    pub nixpacks_version: String,
+    pub providers: Vec<String>,
+    pub build_image: Option<String>,
+    pub build_image_digest: Option<String>,
+    pub phases: BTreeMap<String, LockedPhase>,
+}
+
+impl Lockfile {
+    /// Create a lock file from a pinned build plan that was generated with `providers`
+    pub fn new(plan: &BuildPlan, providers: Vec<String>) -> Self {
+        let phases = plan
+            .phases
+            .clone()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(name, phase)| {
+                let locked = LockedPhase {
+                    nixpkgs_archive: phase.nixpkgs_archive,
+                    nix_pkgs: phase.nix_pkgs,
+                    nix_libs: phase.nix_libs,
+                    nix_overlays: phase.nix_overlays,
+                    apt_pkgs: phase.apt_pkgs,
+                };
+                (name, locked)
+            })
+            .filter(|(_, locked)| locked != &LockedPhase::default())
+            .collect();
+
+        Lockfile {
+            version: LOCKFILE_VERSION,
+            nixpacks_version: NIX_PACKS_VERSION.to_string(),
+            providers,
+            build_image: plan.build_image.clone(),
+            build_image_digest: None,
+            phases,
+        }
+    }
+
+    pub fn from_toml<S: Into<String>>(toml: S) -> Result<Self> {
+        Ok(toml::from_str(&toml.into())?)
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(format!(
+            "{LOCKFILE_HEADER}{}",
+            toml::to_string_pretty(self)?
+        ))
+    }
+
+    /// Read the lock file from the app directory, if there is one
+    pub fn read(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(LOCKFILE_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let contents = fs::read_to_string(&path).context("Failed to read nixpacks.lock")?;
+        let lockfile = Lockfile::from_toml(contents).context("Failed to parse nixpacks.lock")?;
+
+        Ok(Some(lockfile))
+    }
+
+    pub fn write(&self, dir: &Path) -> Result<()> {
+        fs::write(dir.join(LOCKFILE_NAME), self.to_toml()?).context("Failed to write nixpacks.lock")
+    }
+
+    /// Whether a freshly generated lock file resolves to different values than this one
+    pub fn is_outdated(&self, current: &Lockfile) -> bool {
+        self.providers != current.providers
+            || self.build_image != current.build_image
+            || self.phases != current.phases
+    }
+
+    /// The build image, pinned to its digest if it is known
+    pub fn get_build_image(&self) -> Option<String> {
+        match (&self.build_image, &self.build_image_digest) {
+            (Some(image), Some(digest)) => Some(format!("{image}@{digest}")),
+            (image, _) => image.clone(),
+        }
+    }
+
+    /// A plan that overrides the resolved values of `plan` with the locked ones
+    pub fn to_plan(&self, plan: &BuildPlan) -> BuildPlan {
+        let mut locked_plan = BuildPlan {
+            build_image: self.get_build_image(),
+            ..Default::default()
+        };
+
+        // Phases that are no longer in the plan are not brought back
+        for (name, locked) in &self.phases {
+            if plan.get_phase(name).is_some() {
+                locked_plan.add_phase(Phase {
+                    name: Some(name.clone()),
+                    nixpkgs_archive: locked.nixpkgs_archive.clone(),
+                    nix_pkgs: locked.nix_pkgs.clone(),
+                    nix_libs: locked.nix_libs.clone(),
+                    nix_overlays: locked.nix_overlays.clone(),
+                    apt_pkgs: locked.apt_pkgs.clone(),
+                    ..Default::default()
+                });
+            }
+        }
+
+        locked_plan
+    }
+
+    /// Look up the digest of the build image with Docker so that it can be pinned.
+    ///
+    /// The image is only pulled when it is not available locally and `pull` is set.
+    pub fn resolve_build_image_digest(&mut self, pull: bool) {
+        self.build_image_digest = self
+            .build_image
+            .as_deref()
+            .and_then(|image| get_image_digest(image, pull));
+    }
+}
+
+fn get_image_digest(image: &str, pull: bool) -> Option<String> {
+    let inspect = || {
+        Command::new("docker")
+            .args([
+                "image",
+                "inspect",
+                "--format",
+                "{{index .RepoDigests 0}}",
+                image,
+            ])
+            .stderr(Stdio::null())
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| {
+                let repo_digest = String::from_utf8_lossy(&output.stdout).trim().to_string();
+                repo_digest
+                    .split_once('@')
+                    .map(|(_, digest)| digest.to_string())
+            })
+    };
+
+    inspect().or_else(|| {
+        if !pull {
+            return None;
+        }
+
+        let pulled = Command::new("docker")
+            .args(["pull", "--quiet", image])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map_or(false, |status| status.success());
+
+        if pulled {
+            inspect()
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_plan() -> BuildPlan {
+        let mut plan = BuildPlan::from_toml(
+            r#"
+            [phases.setup]
+            nixPkgs = ["nodejs-16_x", "npm-8_x"]
+            aptPkgs = ["wget"]
+
+            [phases.build]
+            cmds = ["npm run build"]
+            "#,
+        )
+        .unwrap();
+        plan.pin();
+        plan
+    }
+
+    #[test]
+    fn test_lockfile_from_plan() {
+        let lockfile = Lockfile::new(&get_plan(), vec!["node".to_string()]);
+
+        assert_eq!(lockfile.providers, vec!["node".to_string()]);
+        assert!(lockfile.build_image.is_some());
+        assert_eq!(lockfile.phases.keys().collect::<Vec<_>>(), vec!["setup"]);
+        assert!(lockfile.phases["setup"].nixpkgs_archive.is_some());
+
+        let roundtrip = Lockfile::from_toml(lockfile.to_toml().unwrap()).unwrap();
+        assert_eq!(lockfile, roundtrip);
+    }
+
+    #[test]
+    fn test_lockfile_to_plan() {
+        let mut lockfile = Lockfile::new(&get_plan(), vec!["node".to_string()]);
+        lockfile.build_image_digest = Some("sha256:abc".to_string());
+        lockfile.phases.get_mut("setup").unwrap().nix_pkgs = Some(vec!["nodejs-14_x".to_string()]);
+        lockfile
+            .phases
+            .insert("removed".to_string(), LockedPhase::default());
+
+        let locked_plan = lockfile.to_plan(&get_plan());
+        assert_eq!(
+            locked_plan.build_image,
+            Some(format!("{}@sha256:abc", lockfile.build_image.unwrap()))
+        );
+        assert_eq!(
+            locked_plan.get_phase("setup").unwrap().nix_pkgs,
+            Some(vec!["nodejs-14_x".to_string()])
+        );
+        assert!(locked_plan.get_phase("removed").is_none());
+    }
+
+    #[test]
+    fn test_lockfile_is_outdated() {
+        let lockfile = Lockfile::new(&get_plan(), vec!["node".to_string()]);
+
+        let mut current = lockfile.clone();
+        current.nixpacks_version = "0.0.0".to_string();
+        current.build_image_digest = Some("sha256:abc".to_string());
+        assert!(!lockfile.is_outdated(&current));
+
+        current.providers = vec!["python".to_string()];
+        assert!(lockfile.is_outdated(&current));
+    }
+}
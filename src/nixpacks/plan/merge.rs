@@ -1,6 +1,7 @@
 use super::{
-    phase::{Phase, StartPhase},
-    utils::fill_auto_in_vec,
+    hooks::Hooks,
+    phase::{Healthcheck, Phase, StartPhase},
+    utils::{fill_auto_in_vec, fill_auto_in_vec_with_removals, REMOVE_CMD_PREFIX, REMOVE_PREFIX},
     BuildPlan,
 };
 
@@ -15,6 +16,16 @@ impl Mergeable for BuildPlan {
 
         new_plan.providers = fill_auto_in_vec(new_plan.providers.clone(), plan2.providers.clone());
         new_plan.build_image = plan2.build_image.or(new_plan.build_image);
+        new_plan.profile = plan2.profile.or(new_plan.profile);
+
+        new_plan.skipped_phases = match (new_plan.skipped_phases, plan2.skipped_phases) {
+            (None, skipped) | (skipped, None) => skipped,
+            (Some(skipped1), Some(skipped2)) => {
+                let mut skipped = skipped1;
+                skipped.extend(skipped2);
+                Some(skipped)
+            }
+        };
 
         new_plan.static_assets = match (new_plan.static_assets, plan2.static_assets) {
             (None, assets) | (assets, None) => assets,
@@ -25,6 +36,8 @@ impl Mergeable for BuildPlan {
             }
         };
 
+        new_plan.exclude = fill_auto_in_vec(new_plan.exclude.clone(), plan2.exclude.clone());
+
         new_plan.variables = match (new_plan.variables, plan2.variables) {
             (None, vars) | (vars, None) => vars,
             (Some(vars1), Some(vars2)) => {
@@ -34,6 +47,15 @@ impl Mergeable for BuildPlan {
             }
         };
 
+        new_plan.variable_scopes = match (new_plan.variable_scopes, plan2.variable_scopes) {
+            (None, scopes) | (scopes, None) => scopes,
+            (Some(scopes1), Some(scopes2)) => {
+                let mut scopes = scopes1;
+                scopes.extend(scopes2);
+                Some(scopes)
+            }
+        };
+
         if new_plan.phases.is_none() {
             new_plan.phases = plan2.phases;
         } else {
@@ -60,6 +82,11 @@ impl Mergeable for BuildPlan {
             (Some(s1), Some(s2)) => Some(StartPhase::merge(&s1, &s2)),
         };
 
+        new_plan.hooks = match (new_plan.hooks, plan2.hooks) {
+            (None, hooks) | (hooks, None) => hooks,
+            (Some(hooks1), Some(hooks2)) => Some(Hooks::merge(&hooks1, &hooks2)),
+        };
+
         new_plan.resolve_phase_names();
         new_plan
     }
@@ -71,22 +98,54 @@ impl Mergeable for Phase {
         let c2 = c2.clone();
         phase.nixpkgs_archive = c2.nixpkgs_archive.or_else(|| phase.nixpkgs_archive.clone());
 
-        phase.cmds = fill_auto_in_vec(phase.cmds.clone(), c2.cmds);
-        phase.depends_on = fill_auto_in_vec(phase.depends_on.clone(), c2.depends_on);
-        phase.nix_pkgs = fill_auto_in_vec(phase.nix_pkgs.clone(), c2.nix_pkgs);
-        phase.nix_libs = fill_auto_in_vec(phase.nix_libs.clone(), c2.nix_libs);
-        phase.apt_pkgs = fill_auto_in_vec(phase.apt_pkgs.clone(), c2.apt_pkgs);
+        phase.cmds = fill_auto_in_vec_with_removals(phase.cmds.clone(), c2.cmds, REMOVE_CMD_PREFIX);
+        phase.depends_on =
+            fill_auto_in_vec_with_removals(phase.depends_on.clone(), c2.depends_on, REMOVE_PREFIX);
+        phase.nix_pkgs =
+            fill_auto_in_vec_with_removals(phase.nix_pkgs.clone(), c2.nix_pkgs, REMOVE_PREFIX);
+        phase.nix_libs =
+            fill_auto_in_vec_with_removals(phase.nix_libs.clone(), c2.nix_libs, REMOVE_PREFIX);
+        phase.apt_pkgs =
+            fill_auto_in_vec_with_removals(phase.apt_pkgs.clone(), c2.apt_pkgs, REMOVE_PREFIX);
         phase.nix_overlays = fill_auto_in_vec(phase.nix_overlays.clone(), c2.nix_overlays);
         phase.only_include_files =
             fill_auto_in_vec(phase.only_include_files.clone(), c2.only_include_files);
-        phase.cache_directories =
-            fill_auto_in_vec(phase.cache_directories.clone(), c2.cache_directories);
+        phase.cache_directories = fill_auto_in_vec_with_removals(
+            phase.cache_directories.clone(),
+            c2.cache_directories,
+            REMOVE_PREFIX,
+        );
         phase.paths = fill_auto_in_vec(phase.paths.clone(), c2.paths);
+        phase.secrets =
+            fill_auto_in_vec_with_removals(phase.secrets.clone(), c2.secrets, REMOVE_PREFIX);
+        phase.variables = match (phase.variables.clone(), c2.variables) {
+            (None, vars) | (vars, None) => vars,
+            (Some(vars1), Some(vars2)) => {
+                let mut vars = vars1;
+                vars.extend(vars2);
+                Some(vars)
+            }
+        };
+        phase.condition = c2.condition.or_else(|| phase.condition.clone());
 
         phase
     }
 }
 
+impl Mergeable for Hooks {
+    fn merge(c1: &Hooks, c2: &Hooks) -> Hooks {
+        let c1 = c1.clone();
+        let c2 = c2.clone();
+        Hooks {
+            pre_install: fill_auto_in_vec(c1.pre_install, c2.pre_install),
+            post_install: fill_auto_in_vec(c1.post_install, c2.post_install),
+            pre_build: fill_auto_in_vec(c1.pre_build, c2.pre_build),
+            post_build: fill_auto_in_vec(c1.post_build, c2.post_build),
+            pre_start: fill_auto_in_vec(c1.pre_start, c2.pre_start),
+        }
+    }
+}
+
 impl Mergeable for StartPhase {
     fn merge(c1: &StartPhase, c2: &StartPhase) -> StartPhase {
         let mut start_phase = c1.clone();
@@ -97,10 +156,58 @@ impl Mergeable for StartPhase {
             start_phase.only_include_files.clone(),
             c2.only_include_files,
         );
+        start_phase.processes = match (start_phase.processes, c2.processes) {
+            (None, processes) | (processes, None) => processes,
+            (Some(processes1), Some(processes2)) => {
+                let mut processes = processes1;
+                processes.extend(processes2);
+                Some(processes)
+            }
+        };
+        start_phase.ports = c2.ports.or_else(|| start_phase.ports.clone());
+        start_phase.user = c2.user.or_else(|| start_phase.user.clone());
+        start_phase.shell = c2.shell.or_else(|| start_phase.shell.clone());
+        start_phase.exec = c2.exec.or(start_phase.exec);
+        start_phase.init = c2.init.or_else(|| start_phase.init.clone());
+        start_phase.entrypoint = c2.entrypoint.or_else(|| start_phase.entrypoint.clone());
+        start_phase.writable_directories = fill_auto_in_vec(
+            start_phase.writable_directories.clone(),
+            c2.writable_directories,
+        );
+        start_phase.healthcheck = match (start_phase.healthcheck, c2.healthcheck) {
+            (None, healthcheck) | (healthcheck, None) => healthcheck,
+            (Some(h1), Some(h2)) => Some(Healthcheck::merge(&h1, &h2)),
+        };
         start_phase
     }
 }
 
+impl Mergeable for Healthcheck {
+    fn merge(c1: &Healthcheck, c2: &Healthcheck) -> Healthcheck {
+        let c1 = c1.clone();
+        let c2 = c2.clone();
+
+        // A command replaces an HTTP check and the other way around
+        let (cmd, path, port) = if c2.cmd.is_some() {
+            (c2.cmd, None, None)
+        } else if c2.path.is_some() {
+            (None, c2.path, c2.port.or(c1.port))
+        } else {
+            (c1.cmd, c1.path, c2.port.or(c1.port))
+        };
+
+        Healthcheck {
+            cmd,
+            path,
+            port,
+            interval: c2.interval.or(c1.interval),
+            timeout: c2.timeout.or(c1.timeout),
+            start_period: c2.start_period.or(c1.start_period),
+            retries: c2.retries.or(c1.retries),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -204,4 +311,116 @@ mod test {
             merged
         );
     }
+
+    #[test]
+    fn test_merge_plan_removals() {
+        let provider_plan = BuildPlan::from_toml(
+            r#"
+            [phases.setup]
+            nixPkgs = ["nodejs", "yarn"]
+            aptPkgs = ["chromium", "wget"]
+
+            [phases.build]
+            dependsOn = ["install", "setup"]
+            cmds = ["yarn run build", "yarn run migrate"]
+            cacheDirectories = ["node_modules/.cache", ".next/cache"]
+            "#,
+        )
+        .unwrap();
+
+        let file_plan = BuildPlan::from_toml(
+            r#"
+            [phases.setup]
+            nixPkgs = ["...", "!yarn", "cowsay"]
+            aptPkgs = [{ remove = "chromium" }]
+
+            [phases.build]
+            dependsOn = ["!setup"]
+            cmds = ["...", { remove = ["yarn run migrate"] }]
+            cacheDirectories = ["!.next/cache"]
+            "#,
+        )
+        .unwrap();
+
+        let mut merged = BuildPlan::merge(&provider_plan, &file_plan);
+        merged.pin();
+
+        let setup = merged.get_phase("setup").unwrap();
+        assert_eq!(
+            setup.nix_pkgs,
+            Some(vec!["nodejs".to_string(), "cowsay".to_string()])
+        );
+        assert_eq!(setup.apt_pkgs, Some(vec!["wget".to_string()]));
+
+        let build = merged.get_phase("build").unwrap();
+        assert_eq!(build.depends_on, Some(vec!["install".to_string()]));
+        assert_eq!(build.cmds, Some(vec!["yarn run build".to_string()]));
+        assert_eq!(
+            build.cache_directories,
+            Some(vec!["node_modules/.cache".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_merge_plan_removals_apply_to_later_merges() {
+        // The config plan is merged with the environment plan before the provider plan
+        let env_plan = BuildPlan::from_toml(
+            r#"
+            [phases.setup]
+            aptPkgs = ["...", "curl"]
+            "#,
+        )
+        .unwrap();
+        let file_plan = BuildPlan::from_toml(
+            r#"
+            [phases.setup]
+            aptPkgs = ["...", "!chromium"]
+            "#,
+        )
+        .unwrap();
+        let provider_plan = BuildPlan::from_toml(
+            r#"
+            [phases.setup]
+            aptPkgs = ["chromium", "wget"]
+            "#,
+        )
+        .unwrap();
+
+        let mut merged = BuildPlan::merge(&provider_plan, &BuildPlan::merge(&file_plan, &env_plan));
+        merged.pin();
+
+        assert_eq!(
+            merged.get_phase("setup").unwrap().apt_pkgs,
+            Some(vec!["wget".to_string(), "curl".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_merge_plan_keeps_cmds_starting_with_bang() {
+        let provider_plan = BuildPlan::from_toml(
+            r#"
+            [phases.build]
+            cmds = ["npm run build", "npm run seed"]
+            "#,
+        )
+        .unwrap();
+        let file_plan = BuildPlan::from_toml(
+            r#"
+            [phases.build]
+            cmds = ["...", "! grep -q debug config.json", { remove = "npm run seed" }]
+            "#,
+        )
+        .unwrap();
+
+        let mut merged = BuildPlan::merge(&provider_plan, &file_plan);
+        merged.pin();
+
+        assert_eq!(
+            merged.get_phase("build").unwrap().cmds,
+            Some(vec![
+                "npm run build".to_string(),
+                "! grep -q debug config.json".to_string()
+            ])
+        );
+    }
 }
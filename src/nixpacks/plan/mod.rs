@@ -1,24 +1,33 @@
 use self::{
+    hooks::Hooks,
     merge::Mergeable,
-    phase::{Phase, Phases, StartPhase},
+    phase::{Phase, Phases, StartPhase, INIT_PKG_SET},
     topological_sort::topological_sort,
+    utils::{interpolate_variables, remove_autos_from_vec, unescape_variables},
 };
 use super::images::DEFAULT_BASE_IMAGE;
 use crate::nixpacks::{
     app::{App, StaticAssets},
-    environment::{Environment, EnvironmentVariables},
+    environment::{Environment, EnvironmentVariables, VariableScope, VariableScopes},
 };
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, ffi::OsStr, fs, path::Path};
 
 // pub mod config;
+pub mod diff;
+pub mod explain;
 pub mod generator;
+pub mod hooks;
+pub mod lock;
 pub mod merge;
 pub mod phase;
 pub mod pretty_print;
+pub mod schema;
 mod topological_sort;
 mod utils;
+pub mod validate;
 
 pub trait PlanGenerator {
     fn generate_plan(&mut self, app: &App, environment: &Environment) -> Result<BuildPlan>;
@@ -26,23 +35,48 @@ pub trait PlanGenerator {
 }
 
 #[serde_with::skip_serializing_none]
-#[derive(PartialEq, Eq, Default, Debug, Serialize, Deserialize, Clone)]
+#[derive(PartialEq, Eq, Default, Debug, Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct BuildPlan {
+    /// Config files (relative to this one) that this plan is merged on top of
+    pub extends: Option<Vec<String>>,
+
     pub providers: Option<Vec<String>>,
 
     #[serde(rename = "buildImage")]
     pub build_image: Option<String>,
 
+    /// The profile whose overlay was applied to the plan. Set by Nixpacks, not by config files.
+    #[schemars(skip)]
+    pub profile: Option<String>,
+
     pub variables: Option<EnvironmentVariables>,
 
+    /// Whether variables are only available while building or at runtime. Variables without a scope are available in both.
+    #[serde(rename = "variableScopes")]
+    pub variable_scopes: Option<VariableScopes>,
+
     #[serde(rename = "staticAssets")]
     pub static_assets: Option<StaticAssets>,
 
+    /// Files that are left out of the build context, in the same format as a `.gitignore`
+    pub exclude: Option<Vec<String>>,
+
     pub phases: Option<Phases>,
 
     #[serde(rename = "start")]
     pub start_phase: Option<StartPhase>,
+
+    /// Commands that are run at fixed points of the build, converted to phases when the plan is generated
+    pub hooks: Option<Hooks>,
+
+    /// Overlays that are merged on top of the plan when their profile is selected
+    #[serde(rename = "env")]
+    pub profiles: Option<BTreeMap<String, BuildPlan>>,
+
+    /// Phases that were removed because their condition was not met, with the condition. Set by Nixpacks, not by config files.
+    #[schemars(skip)]
+    pub skipped_phases: Option<BTreeMap<String, String>>,
 }
 
 impl BuildPlan {
@@ -66,6 +100,21 @@ impl BuildPlan {
         Ok(plan)
     }
 
+    /// Read a plan from a `.json` or `.toml` file, e.g. the output of `nixpacks plan`
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read plan file `{}`", path.display()))?;
+
+        let plan = match path.extension().and_then(OsStr::to_str) {
+            Some("toml") => BuildPlan::from_toml(contents),
+            Some("json") => BuildPlan::from_json(contents),
+            _ => bail!("Unknown plan file type: {}", path.display()),
+        }
+        .with_context(|| format!("Failed to parse plan file `{}`", path.display()))?;
+
+        Ok(plan)
+    }
+
     pub fn to_toml(&self) -> Result<String> {
         let mut plan = self.clone();
         plan.remove_phase_names();
@@ -87,6 +136,57 @@ impl BuildPlan {
         self.start_phase = Some(start_phase);
     }
 
+    /// A plan that installs the init of the start phase along with the other Nix packages.
+    /// Nothing is added when the setup phase already has it, e.g. from the lock file.
+    #[must_use]
+    pub fn get_init_plan(&self) -> BuildPlan {
+        let init = match self
+            .start_phase
+            .as_ref()
+            .and_then(|start| start.init.clone())
+        {
+            Some(init) => init,
+            None => return BuildPlan::default(),
+        };
+
+        let init_pkg = format!("{INIT_PKG_SET}.{init}");
+        let is_installed = self
+            .get_phase("setup")
+            .and_then(|setup| setup.nix_pkgs.as_ref())
+            .map_or(false, |pkgs| pkgs.contains(&init_pkg));
+        if is_installed {
+            return BuildPlan::default();
+        }
+
+        let setup = Phase {
+            name: Some("setup".to_string()),
+            nix_pkgs: Some(vec!["...".to_string(), init_pkg]),
+            ..Default::default()
+        };
+        let mut init_plan = BuildPlan::default();
+        init_plan.add_phase(setup);
+        init_plan
+    }
+
+    /// `PORT` set to the default port of the start phase, unless the plan already sets it
+    pub fn get_port_variables(&self) -> EnvironmentVariables {
+        let has_port = self
+            .variables
+            .as_ref()
+            .map_or(false, |variables| variables.contains_key("PORT"));
+
+        match self
+            .start_phase
+            .as_ref()
+            .and_then(StartPhase::get_default_port)
+        {
+            Some(port) if !has_port => {
+                EnvironmentVariables::from([("PORT".to_string(), port.to_string())])
+            }
+            _ => EnvironmentVariables::new(),
+        }
+    }
+
     pub fn add_variables(&mut self, variables: EnvironmentVariables) {
         match self.variables.as_mut() {
             Some(vars) => {
@@ -100,6 +200,35 @@ impl BuildPlan {
         }
     }
 
+    /// Add variables that are only available in the given scope
+    pub fn add_scoped_variables(&mut self, variables: EnvironmentVariables, scope: VariableScope) {
+        self.add_variable_scopes(variables.keys().map(|name| (name.clone(), scope)).collect());
+        self.add_variables(variables);
+    }
+
+    pub fn add_variable_scopes(&mut self, scopes: VariableScopes) {
+        self.variable_scopes
+            .get_or_insert(VariableScopes::default())
+            .extend(scopes);
+    }
+
+    pub fn get_variable_scope(&self, name: &str) -> VariableScope {
+        self.variable_scopes
+            .as_ref()
+            .and_then(|scopes| scopes.get(name).copied())
+            .unwrap_or_default()
+    }
+
+    /// Names of the variables that are available in exactly the given scope
+    pub fn get_variables_in_scope(&self, scope: VariableScope) -> Vec<String> {
+        self.variables
+            .clone()
+            .unwrap_or_default()
+            .into_keys()
+            .filter(|name| self.get_variable_scope(name) == scope)
+            .collect()
+    }
+
     pub fn add_static_assets(&mut self, static_assets: StaticAssets) {
         match self.static_assets.as_mut() {
             Some(assets) => {
@@ -128,6 +257,61 @@ impl BuildPlan {
         self.phases.get_or_insert(BTreeMap::default()).remove(name)
     }
 
+    /// Remove the phases whose `if` condition is not met, along with any dependencies on them
+    pub fn remove_skipped_phases(&mut self, app: &App) -> Result<()> {
+        let variables = self.variables.clone().unwrap_or_default();
+
+        let names = self.phases.clone().unwrap_or_default().into_keys();
+        for name in names {
+            // Read the phase again, as skipping an earlier phase can change what it depends on
+            let phase = match self.get_phase(&name) {
+                Some(phase) => phase.clone(),
+                None => continue,
+            };
+            let condition = match &phase.condition {
+                Some(condition) => condition,
+                None => continue,
+            };
+
+            let is_met = condition
+                .is_met(app, &variables)
+                .with_context(|| format!("Invalid condition for phase `{name}`"))?;
+            if is_met {
+                continue;
+            }
+
+            self.remove_phase(&name);
+            self.skipped_phases
+                .get_or_insert_with(BTreeMap::default)
+                .insert(name.clone(), condition.to_string());
+
+            // Phases that depended on the skipped phase now depend on what it depended on
+            let inherited = phase.depends_on.clone().unwrap_or_default();
+            for other in self.phases.get_or_insert(BTreeMap::default()).values_mut() {
+                if let Some(depends_on) = &other.depends_on {
+                    if depends_on.contains(&name) {
+                        let mut new_depends_on = Vec::new();
+                        for dependency in depends_on {
+                            let replacement = if dependency == &name {
+                                inherited.clone()
+                            } else {
+                                vec![dependency.clone()]
+                            };
+                            for d in replacement {
+                                if !new_depends_on.contains(&d) {
+                                    new_depends_on.push(d);
+                                }
+                            }
+                        }
+                        other.depends_on = Some(new_depends_on);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn get_sorted_phases(&self) -> Result<Vec<Phase>> {
         let phases_with_names = self
             .phases
@@ -263,7 +447,10 @@ impl BuildPlan {
         }
 
         // Start
-        let start = env.get_config_variable("START_CMD").map(StartPhase::new);
+        let mut start = env.get_config_variable("START_CMD").map(StartPhase::new);
+        if let Some(user) = env.get_config_variable("USER") {
+            start.get_or_insert_with(StartPhase::default).user = Some(user);
+        }
 
         BuildPlan::new(&phases, start)
     }
@@ -274,17 +461,73 @@ impl BuildPlan {
             self.build_image = Some(DEFAULT_BASE_IMAGE.to_string());
         }
 
+        self.exclude = self.exclude.take().map(remove_autos_from_vec);
+
         self.resolve_phase_names();
         let phases = self.phases.get_or_insert(Phases::default());
         for (_, phase) in phases.iter_mut() {
             phase.pin();
         }
 
+        self.interpolate_variables();
+
         if let Some(start) = &mut self.start_phase {
             start.pin();
         }
     }
 
+    /// Names of the variables that are referenced with `${VAR}` but not defined in the plan
+    pub fn get_undefined_variables(&self) -> Vec<String> {
+        self.clone().interpolate_variables()
+    }
+
+    /// Resolve the `${VAR}` references in the commands, paths and cache directories of every phase.
+    /// The variables of a phase take precedence and references to its secrets are left for the shell.
+    ///
+    /// Returns the names of the referenced variables that are not defined.
+    fn interpolate_variables(&mut self) -> Vec<String> {
+        let plan_variables = self.variables.clone().unwrap_or_default();
+        let mut undefined = Vec::new();
+
+        for phase in self.phases.get_or_insert(Phases::default()).values_mut() {
+            let mut variables = plan_variables.clone();
+            variables.extend(phase.variables.clone().unwrap_or_default());
+            let secrets = phase.secrets.clone().unwrap_or_default();
+
+            let mut interpolate = |values: &mut Option<Vec<String>>| {
+                for value in values.iter_mut().flatten() {
+                    let (interpolated, missing) = interpolate_variables(value, &variables);
+                    *value = interpolated;
+                    undefined.extend(missing.into_iter().filter(|name| !secrets.contains(name)));
+                }
+            };
+
+            interpolate(&mut phase.cmds);
+            interpolate(&mut phase.paths);
+            interpolate(&mut phase.cache_directories);
+        }
+
+        undefined.sort();
+        undefined.dedup();
+        undefined
+    }
+
+    /// Turn the escaped `$${VAR}` references that are kept in the pinned plan into a literal `${VAR}`,
+    /// for when the plan is turned into an image
+    pub fn unescape_variables(&mut self) {
+        for phase in self.phases.get_or_insert(Phases::default()).values_mut() {
+            for values in [
+                &mut phase.cmds,
+                &mut phase.paths,
+                &mut phase.cache_directories,
+            ] {
+                for value in values.iter_mut().flatten() {
+                    *value = unescape_variables(value);
+                }
+            }
+        }
+    }
+
     pub fn prefix_phases(&mut self, prefix: &str) {
         if let Some(phases) = self.phases.clone() {
             self.resolve_phase_names();
@@ -429,4 +672,181 @@ mod test {
             vec!["nodejs".to_string(), "yarn".to_string()]
         );
     }
+
+    #[test]
+    fn test_remove_skipped_phases() {
+        let mut plan = BuildPlan::from_toml(
+            r#"
+            [variables]
+            NODE_ENV = "production"
+
+            [phases.build]
+            cmds = ["npm run build"]
+
+            [phases.migrate]
+            dependsOn = ["build"]
+            cmds = ["npm run migrate"]
+            if = { env = "DATABASE_URL" }
+
+            [phases.seed]
+            dependsOn = ["migrate"]
+            cmds = ["npm run seed"]
+            if = { file = "package.json", env = "NODE_ENV", equals = "production" }
+            "#,
+        )
+        .unwrap();
+
+        let app = App::new("./examples/node").unwrap();
+        plan.remove_skipped_phases(&app).unwrap();
+
+        assert!(plan.get_phase("migrate").is_none());
+        assert_eq!(
+            plan.get_phase("seed").unwrap().depends_on,
+            Some(vec!["build".to_string()])
+        );
+        assert_eq!(
+            plan.skipped_phases,
+            Some(BTreeMap::from([(
+                "migrate".to_string(),
+                "env DATABASE_URL".to_string()
+            )]))
+        );
+    }
+
+    #[test]
+    fn test_remove_chained_skipped_phases() {
+        let mut plan = BuildPlan::from_toml(
+            r#"
+            [phases.build]
+            cmds = ["npm run build"]
+
+            [phases.migrate]
+            dependsOn = ["build"]
+            cmds = ["npm run migrate"]
+            if = { env = "DATABASE_URL" }
+
+            [phases.seed]
+            dependsOn = ["migrate"]
+            cmds = ["npm run seed"]
+            if = { env = "SEED" }
+
+            [phases.zfinal]
+            dependsOn = ["seed"]
+            cmds = ["npm run final"]
+            "#,
+        )
+        .unwrap();
+
+        let app = App::new("./examples/node").unwrap();
+        plan.remove_skipped_phases(&app).unwrap();
+
+        assert!(plan.get_phase("migrate").is_none());
+        assert!(plan.get_phase("seed").is_none());
+        assert_eq!(
+            plan.get_phase("zfinal").unwrap().depends_on,
+            Some(vec!["build".to_string()])
+        );
+        assert!(plan.get_sorted_phases().is_ok());
+    }
+
+    #[test]
+    fn test_invalid_phase_condition() {
+        let mut plan = BuildPlan::from_toml(
+            r#"
+            [phases.migrate]
+            if = { equals = "production" }
+            "#,
+        )
+        .unwrap();
+
+        let app = App::new("./examples/node").unwrap();
+        assert!(plan.remove_skipped_phases(&app).is_err());
+    }
+
+    #[test]
+    fn test_get_port_variables() {
+        let mut plan = BuildPlan::from_toml(
+            r#"
+            [start]
+            cmd = "npm run start"
+            ports = [3000, 9229]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            plan.get_port_variables(),
+            EnvironmentVariables::from([("PORT".to_string(), "3000".to_string())])
+        );
+
+        plan.add_variables(EnvironmentVariables::from([(
+            "PORT".to_string(),
+            "8080".to_string(),
+        )]));
+        assert!(plan.get_port_variables().is_empty());
+    }
+
+    #[test]
+    fn test_init_plan_installs_static_init_for_run_image() {
+        let plan = BuildPlan::from_toml(
+            r#"
+            [start]
+            cmd = "node index.js"
+            runImage = "debian:bullseye-slim"
+            init = "tini"
+            "#,
+        )
+        .unwrap();
+
+        let init_plan = plan.get_init_plan();
+        assert_eq!(
+            init_plan.get_phase("setup").unwrap().nix_pkgs,
+            Some(vec!["...".to_string(), "pkgsStatic.tini".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_generated_fields_round_trip() {
+        let plan = BuildPlan {
+            phases: Some(BTreeMap::from([("build".to_string(), Phase::new("build"))])),
+            profile: Some("production".to_string()),
+            skipped_phases: Some(BTreeMap::from([(
+                "migrate".to_string(),
+                "env DATABASE_URL".to_string(),
+            )])),
+            ..Default::default()
+        };
+
+        assert_eq!(BuildPlan::from_json(plan.to_json().unwrap()).unwrap(), plan);
+        assert_eq!(BuildPlan::from_toml(plan.to_toml().unwrap()).unwrap(), plan);
+    }
+
+    #[test]
+    fn test_interpolation_survives_repinning() {
+        let mut plan = BuildPlan::from_toml(
+            r#"
+            [variables]
+            APP_NAME = "my-app"
+
+            [phases.build]
+            cmds = ["cp -r dist /srv/${APP_NAME}", "echo $${APP_NAME}"]
+            "#,
+        )
+        .unwrap();
+        plan.pin();
+
+        // e.g. a plan that is passed back in with `--json-plan`
+        let mut repinned = BuildPlan::from_json(plan.to_json().unwrap()).unwrap();
+        repinned.pin();
+        assert_eq!(repinned, plan);
+
+        repinned.unescape_variables();
+        assert_eq!(
+            repinned.get_phase("build").unwrap().cmds,
+            Some(vec![
+                "cp -r dist /srv/my-app".to_string(),
+                "echo ${APP_NAME}".to_string()
+            ])
+        );
+    }
 }
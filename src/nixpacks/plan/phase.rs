@@ -1,27 +1,58 @@
 use crate::nixpacks::{
+    app::App,
+    environment::EnvironmentVariables,
     images::{DEBIAN_SLIM_IMAGE, DEFAULT_BASE_IMAGE},
     nix::{pkg::Pkg, NIXPKGS_ARCHIVE},
 };
-use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use anyhow::{bail, Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+};
 
-use super::utils::remove_autos_from_vec;
+use super::utils::{
+    remove_autos_from_vec, remove_removals_from_vec, REMOVE_CMD_PREFIX, REMOVE_PREFIX,
+};
 
 pub type Phases = BTreeMap<String, Phase>;
 
+/// The init processes that can be installed from Nix, which provide a binary of the same name
+pub const SUPPORTED_INITS: &[&str] = &["tini", "dumb-init"];
+
+/// The Nix package set that inits are installed from. They are statically linked, so the binary
+/// still runs when it is copied into a run image without the Nix store
+pub const INIT_PKG_SET: &str = "pkgsStatic";
+
+/// Where the binary of the init is copied to in the image
+pub const INIT_PATH: &str = "/usr/local/bin/nixpacks-init";
+
+/// Where the script that starts the named processes is put in the image
+pub const PROCESS_LAUNCHER: &str = "/usr/local/bin/nixpacks-start";
+
+const DEFAULT_SHELL: &[&str] = &["/bin/bash", "-l", "-c"];
+pub type Processes = BTreeMap<String, String>;
+
 #[serde_with::skip_serializing_none]
-#[derive(PartialEq, Eq, Serialize, Deserialize, Default, Clone, Debug)]
+#[derive(PartialEq, Eq, Serialize, Deserialize, Default, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Phase {
     pub name: Option<String>,
 
     #[serde(rename = "dependsOn")]
+    #[serde(default, deserialize_with = "deserialize_list")]
+    #[schemars(with = "Option<Vec<ListItem>>")]
     pub depends_on: Option<Vec<String>>,
 
     #[serde(alias = "nixPackages")]
+    #[serde(default, deserialize_with = "deserialize_list")]
+    #[schemars(with = "Option<Vec<ListItem>>")]
     pub nix_pkgs: Option<Vec<String>>,
 
     #[serde(alias = "nixLibraries")]
+    #[serde(default, deserialize_with = "deserialize_list")]
+    #[schemars(with = "Option<Vec<ListItem>>")]
     pub nix_libs: Option<Vec<String>>,
 
     pub nix_overlays: Option<Vec<String>>,
@@ -29,28 +60,200 @@ pub struct Phase {
     pub nixpkgs_archive: Option<String>,
 
     #[serde(alias = "aptPackages")]
+    #[serde(default, deserialize_with = "deserialize_list")]
+    #[schemars(with = "Option<Vec<ListItem>>")]
     pub apt_pkgs: Option<Vec<String>>,
 
     #[serde(alias = "commands")]
+    #[serde(default, deserialize_with = "deserialize_cmds")]
+    #[schemars(with = "Option<Vec<ListItem>>")]
     pub cmds: Option<Vec<String>>,
 
     #[serde(rename = "onlyIncludeFiles")]
     pub only_include_files: Option<Vec<String>>,
 
     #[serde(rename = "cacheDirectories")]
+    #[serde(default, deserialize_with = "deserialize_list")]
+    #[schemars(with = "Option<Vec<ListItem>>")]
     pub cache_directories: Option<Vec<String>>,
 
     #[serde(alias = "envPaths")]
     pub paths: Option<Vec<String>>,
+
+    /// Variables that are only set for the commands of this phase
+    pub variables: Option<EnvironmentVariables>,
+
+    /// Names of the secrets that are mounted for the commands of this phase
+    #[serde(default, deserialize_with = "deserialize_list")]
+    #[schemars(with = "Option<Vec<ListItem>>")]
+    pub secrets: Option<Vec<String>>,
+
+    /// Only run the phase when this condition is met
+    #[serde(rename = "if")]
+    pub condition: Option<PhaseCondition>,
 }
 
+/// A condition that is evaluated when the plan is generated, which skips the whole phase when it is not met.
+///
+/// All the given checks must pass for the condition to be met.
 #[serde_with::skip_serializing_none]
-#[derive(PartialEq, Eq, Serialize, Deserialize, Default, Clone, Debug)]
+#[derive(PartialEq, Eq, Serialize, Deserialize, Default, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PhaseCondition {
+    /// A file or directory that must exist in the app
+    pub file: Option<String>,
+
+    /// A variable that must be set to a non-empty value
+    pub env: Option<String>,
+
+    /// The value that the `env` variable must be equal to
+    pub equals: Option<String>,
+}
+
+impl PhaseCondition {
+    pub fn is_met(&self, app: &App, variables: &EnvironmentVariables) -> Result<bool> {
+        if self.file.is_none() && self.env.is_none() {
+            bail!("A phase condition needs a `file` or `env` to check");
+        }
+
+        if let Some(file) = &self.file {
+            if !app.includes_file(file) && !app.includes_directory(file) {
+                return Ok(false);
+            }
+        }
+
+        match (&self.env, &self.equals) {
+            (Some(name), Some(expected)) => Ok(variables.get(name) == Some(expected)),
+            (Some(name), None) => Ok(variables.get(name).map_or(false, |v| !v.is_empty())),
+            (None, Some(_)) => bail!("`equals` in a phase condition needs an `env` to compare"),
+            (None, None) => Ok(true),
+        }
+    }
+}
+
+impl Display for PhaseCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut checks = Vec::new();
+        if let Some(file) = &self.file {
+            checks.push(format!("file {file}"));
+        }
+        match (&self.env, &self.equals) {
+            (Some(name), Some(expected)) => checks.push(format!("env {name} == {expected}")),
+            (Some(name), None) => checks.push(format!("env {name}")),
+            _ => {}
+        }
+
+        write!(f, "{}", checks.join(" and "))
+    }
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(PartialEq, Eq, Serialize, Deserialize, Default, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct StartPhase {
     pub cmd: Option<String>,
     pub run_image: Option<String>,
     pub only_include_files: Option<Vec<String>>,
+    pub processes: Option<Processes>,
+    pub ports: Option<Vec<u16>>,
+    pub healthcheck: Option<Healthcheck>,
+
+    /// User that the container runs as instead of root
+    pub user: Option<String>,
+
+    /// Directories that the user needs to be able to write to
+    pub writable_directories: Option<Vec<String>>,
+
+    /// Shell that runs the start command, e.g. `["/bin/sh", "-c"]`
+    pub shell: Option<Vec<String>>,
+
+    /// Run the start command without a shell, so that it receives signals directly
+    pub exec: Option<bool>,
+
+    /// Init process that runs as PID 1 and forwards signals to the app, `tini` or `dumb-init`
+    pub init: Option<String>,
+
+    /// Replaces the entrypoint of the image, including the shell and init
+    pub entrypoint: Option<Vec<String>>,
+}
+
+/// How Docker checks that a running container is still healthy
+#[serde_with::skip_serializing_none]
+#[derive(PartialEq, Eq, Serialize, Deserialize, Default, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Healthcheck {
+    /// Command that exits with 0 when the app is healthy
+    pub cmd: Option<String>,
+
+    /// Path that is requested over HTTP when no command is given
+    pub path: Option<String>,
+
+    /// Port that the path is requested on when `$PORT` is not set
+    pub port: Option<u16>,
+
+    pub interval: Option<String>,
+    pub timeout: Option<String>,
+    pub start_period: Option<String>,
+    pub retries: Option<u32>,
+}
+
+/// A value in a list, or values to remove from the list that is being extended
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum ListItem {
+    Value(String),
+    Remove { remove: RemoveValues },
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum RemoveValues {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Deserialize a list where `{ remove = ... }` items are stored as `"!value"` markers
+fn deserialize_list<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_list_with_prefix(deserializer, REMOVE_PREFIX)
+}
+
+/// Deserialize commands, which can only be removed with `{ remove = ... }` because commands can start with `!`
+fn deserialize_cmds<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_list_with_prefix(deserializer, REMOVE_CMD_PREFIX)
+}
+
+fn deserialize_list_with_prefix<'de, D>(
+    deserializer: D,
+    prefix: &str,
+) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let items = Option::<Vec<ListItem>>::deserialize(deserializer)?;
+
+    Ok(items.map(|items| {
+        items
+            .into_iter()
+            .flat_map(|item| match item {
+                ListItem::Value(value) => vec![value],
+                ListItem::Remove {
+                    remove: RemoveValues::One(value),
+                } => vec![format!("{prefix}{value}")],
+                ListItem::Remove {
+                    remove: RemoveValues::Many(values),
+                } => values
+                    .into_iter()
+                    .map(|value| format!("{prefix}{value}"))
+                    .collect(),
+            })
+            .collect()
+    }))
 }
 
 impl Phase {
@@ -181,6 +384,16 @@ impl Phase {
         self.paths = Some(add_to_option_vec(self.paths.clone(), path));
     }
 
+    pub fn add_variable<S: Into<String>>(&mut self, name: S, value: S) {
+        self.variables
+            .get_or_insert(EnvironmentVariables::default())
+            .insert(name.into(), value.into());
+    }
+
+    pub fn add_secret<S: Into<String>>(&mut self, name: S) {
+        self.secrets = Some(add_to_option_vec(self.secrets.clone(), name.into()));
+    }
+
     pub fn set_nix_archive(&mut self, archive: String) {
         self.nixpkgs_archive = Some(archive);
     }
@@ -190,15 +403,23 @@ impl Phase {
             self.nixpkgs_archive = Some(NIXPKGS_ARCHIVE.to_string());
         }
 
-        self.cmds = pin_option_vec(&self.cmds);
-        self.depends_on = pin_option_vec(&self.depends_on);
-        self.nix_pkgs = pin_option_vec(&self.nix_pkgs);
-        self.nix_libs = pin_option_vec(&self.nix_libs);
-        self.apt_pkgs = pin_option_vec(&self.apt_pkgs);
+        self.cmds = pin_option_vec(&self.cmds)
+            .map(|cmds| remove_removals_from_vec(cmds, REMOVE_CMD_PREFIX));
+        self.depends_on = pin_option_vec(&self.depends_on)
+            .map(|values| remove_removals_from_vec(values, REMOVE_PREFIX));
+        self.nix_pkgs = pin_option_vec(&self.nix_pkgs)
+            .map(|values| remove_removals_from_vec(values, REMOVE_PREFIX));
+        self.nix_libs = pin_option_vec(&self.nix_libs)
+            .map(|values| remove_removals_from_vec(values, REMOVE_PREFIX));
+        self.apt_pkgs = pin_option_vec(&self.apt_pkgs)
+            .map(|values| remove_removals_from_vec(values, REMOVE_PREFIX));
         self.nix_overlays = pin_option_vec(&self.nix_overlays);
         self.only_include_files = pin_option_vec(&self.only_include_files);
-        self.cache_directories = pin_option_vec(&self.cache_directories);
+        self.cache_directories = pin_option_vec(&self.cache_directories)
+            .map(|values| remove_removals_from_vec(values, REMOVE_PREFIX));
         self.paths = pin_option_vec(&self.paths);
+        self.secrets = pin_option_vec(&self.secrets)
+            .map(|values| remove_removals_from_vec(values, REMOVE_PREFIX));
     }
 }
 
@@ -229,8 +450,159 @@ impl StartPhase {
         ));
     }
 
+    pub fn add_process<S: Into<String>>(&mut self, name: S, cmd: S) {
+        self.processes
+            .get_or_insert(Processes::default())
+            .insert(name.into(), cmd.into());
+    }
+
+    pub fn add_writable_directory<S: Into<String>>(&mut self, dir: S) {
+        self.writable_directories = Some(add_to_option_vec(
+            self.writable_directories.clone(),
+            dir.into(),
+        ));
+    }
+
+    pub fn add_port(&mut self, port: u16) {
+        self.ports = Some(add_to_option_vec(self.ports.clone(), port));
+    }
+
+    /// The shell that the start command is run with
+    pub fn get_shell(&self) -> Vec<String> {
+        self.shell
+            .clone()
+            .unwrap_or_else(|| DEFAULT_SHELL.iter().map(ToString::to_string).collect())
+    }
+
+    pub fn is_exec(&self) -> bool {
+        self.exec.unwrap_or(false)
+    }
+
+    /// The entrypoint of the image, which the start command or process name is passed to
+    pub fn get_entrypoint(&self) -> Vec<String> {
+        if let Some(entrypoint) = &self.entrypoint {
+            return entrypoint.clone();
+        }
+
+        let mut entrypoint = Vec::new();
+        if self.init.is_some() {
+            entrypoint.extend([INIT_PATH.to_string(), "--".to_string()]);
+        }
+        if !self.is_exec() {
+            entrypoint.extend(self.get_shell());
+        }
+
+        entrypoint
+    }
+
+    /// The entrypoint that runs the process launcher at `launcher` with the shell of the start phase
+    pub fn get_launcher_entrypoint(&self, launcher: &str) -> Vec<String> {
+        if let Some(entrypoint) = &self.entrypoint {
+            return entrypoint.clone();
+        }
+
+        let mut entrypoint = Vec::new();
+        if self.init.is_some() {
+            entrypoint.extend([INIT_PATH.to_string(), "--".to_string()]);
+        }
+
+        // The launcher is a script, so the shell runs it as a file instead of a command string
+        let mut shell = self.get_shell();
+        if shell.last().map(String::as_str) == Some("-c") {
+            shell.pop();
+        }
+        entrypoint.extend(shell);
+        entrypoint.push(launcher.to_string());
+
+        entrypoint
+    }
+
+    /// The arguments that are passed to the entrypoint when no command is given.
+    /// In exec form the start command is split into its arguments like a shell would.
+    pub fn get_command(&self) -> Result<Vec<String>> {
+        if self.has_processes() {
+            return Ok(self.get_default_process().into_iter().collect());
+        }
+
+        match &self.cmd {
+            Some(cmd) if self.is_exec() => shell_words::split(cmd).context(format!(
+                "Failed to split start command `{cmd}` into arguments"
+            )),
+            Some(cmd) => Ok(vec![cmd.clone()]),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Whether the image needs a different entrypoint than the one of the build image
+    pub fn has_custom_entrypoint(&self) -> bool {
+        self.get_entrypoint() != DEFAULT_SHELL
+    }
+
+    /// The port that the app listens on when `PORT` is not set
+    pub fn get_default_port(&self) -> Option<u16> {
+        self.ports.as_ref().and_then(|ports| ports.first().copied())
+    }
+
+    pub fn has_processes(&self) -> bool {
+        !self.processes.clone().unwrap_or_default().is_empty()
+    }
+
+    /// The process (or command) that a container runs when none is given.
+    ///
+    /// This is the name of the process matching the start command, then the start command itself,
+    /// then the `web` process and finally the first named process.
+    pub fn get_default_process(&self) -> Option<String> {
+        let processes = self.processes.clone().unwrap_or_default();
+
+        if let Some(cmd) = &self.cmd {
+            let name = processes
+                .iter()
+                .find(|(_, process_cmd)| *process_cmd == cmd)
+                .map(|(name, _)| name.clone());
+            return Some(name.unwrap_or_else(|| cmd.clone()));
+        }
+
+        if processes.contains_key("web") {
+            return Some("web".to_string());
+        }
+
+        processes.keys().next().cloned()
+    }
+
     pub fn pin(&mut self) {
         self.only_include_files = pin_option_vec(&self.only_include_files);
+        self.writable_directories = pin_option_vec(&self.writable_directories);
+    }
+}
+
+impl Healthcheck {
+    /// Check that a request to `path` on the app's port succeeds
+    pub fn http<S: Into<String>>(path: S, port: u16) -> Self {
+        Self {
+            path: Some(path.into()),
+            port: Some(port),
+            ..Default::default()
+        }
+    }
+
+    /// The command that is run to check the container, if there is enough to build one
+    pub fn get_cmd(&self) -> Option<String> {
+        if let Some(cmd) = &self.cmd {
+            return Some(cmd.clone());
+        }
+
+        self.path.as_ref().map(|path| {
+            let path = if path.starts_with('/') {
+                path.clone()
+            } else {
+                format!("/{path}")
+            };
+            let port = match self.port {
+                Some(port) => format!("${{PORT:-{port}}}"),
+                None => "${PORT}".to_string(),
+            };
+            format!("curl -fsS http://localhost:{port}{path} || exit 1")
+        })
     }
 }
 
@@ -11,7 +11,10 @@ const MAX_BOX_WIDTH: usize = 80;
 
 impl BuildPlan {
     pub fn get_build_string(&self) -> Result<String> {
-        let title_str = format!(" Nixpacks v{} ", NIX_PACKS_VERSION);
+        let title_str = match &self.profile {
+            Some(profile) => format!(" Nixpacks v{NIX_PACKS_VERSION} ({profile}) "),
+            None => format!(" Nixpacks v{} ", NIX_PACKS_VERSION),
+        };
         let title_width = console::measure_text_width(title_str.as_str());
 
         let phase_contents = self
@@ -19,14 +22,27 @@ impl BuildPlan {
             .iter()
             .filter(|phase| phase.uses_nix() || phase.apt_pkgs.is_some() || phase.cmds.is_some())
             .map(|phase| (phase.get_name(), self.get_phase_content(phase).unwrap()))
+            .chain(
+                self.skipped_phases
+                    .clone()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(name, condition)| (name, format!("skipped (if {condition})"))),
+            )
             .collect::<Vec<_>>();
 
-        let start_contents = self
-            .start_phase
-            .clone()
-            .unwrap_or_default()
-            .cmd
-            .unwrap_or_default();
+        let start_phase = self.start_phase.clone().unwrap_or_default();
+        let start_contents = [
+            start_phase.cmd.into_iter().collect::<Vec<_>>(),
+            start_phase
+                .processes
+                .unwrap_or_default()
+                .iter()
+                .map(|(name, cmd)| format!("{name}: {cmd}"))
+                .collect(),
+        ]
+        .concat()
+        .join("\n");
 
         let max_right_content = phase_contents
             .iter()
@@ -40,7 +56,11 @@ impl BuildPlan {
             .unwrap_or(0);
         let max_right_content = std::cmp::max(
             max_right_content,
-            console::measure_text_width(start_contents.as_str()),
+            start_contents
+                .split('\n')
+                .map(console::measure_text_width)
+                .max()
+                .unwrap_or(0),
         );
 
         let first_column_width = std::cmp::max(
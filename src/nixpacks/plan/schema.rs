@@ -0,0 +1,321 @@
+use super::BuildPlan;
+use crate::nixpacks::nix::pkg::Pkg;
+use anyhow::{bail, Result};
+use schemars::{gen::SchemaSettings, schema::RootSchema};
+use serde_json::Value;
+use std::fmt::{self, Display};
+
+/// Maximum edit distance for a known key to be suggested in place of an unknown one
+const MAX_SUGGESTION_DISTANCE: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Json,
+}
+
+/// A key in a config file that does not exist in the build plan
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownKey {
+    pub file: String,
+    pub path: Vec<String>,
+    pub line: Option<usize>,
+    pub suggestion: Option<String>,
+}
+
+impl Display for UnknownKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{line}: ", self.file)?,
+            None => write!(f, "{}: ", self.file)?,
+        }
+
+        let (key, parent) = self.path.split_last().unwrap();
+        if parent.is_empty() {
+            write!(f, "Unknown key `{key}`")?;
+        } else {
+            write!(f, "Unknown key `{key}` in `{}`", parent.join("."))?;
+        }
+
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, ". Did you mean `{suggestion}`?")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The JSON Schema describing `nixpacks.toml` and `nixpacks.json` files
+pub fn plan_schema() -> RootSchema {
+    let mut gen = SchemaSettings::draft07().into_generator();
+    gen.subschema_for::<Pkg>();
+
+    let mut schema = gen.into_root_schema_for::<BuildPlan>();
+    schema.schema.metadata().title = Some("Nixpacks build plan".to_string());
+    schema
+}
+
+/// Find all the keys in a config file that are not part of the build plan
+pub fn find_unknown_keys(
+    contents: &str,
+    file: &str,
+    format: ConfigFormat,
+) -> Result<Vec<UnknownKey>> {
+    let mut paths: Vec<Vec<String>> = Vec::new();
+    let mut collect = |path: serde_ignored::Path| paths.push(path_segments(&path));
+
+    match format {
+        ConfigFormat::Toml => {
+            let mut de = toml::Deserializer::new(contents);
+            serde_ignored::deserialize::<_, _, BuildPlan>(&mut de, &mut collect)?;
+        }
+        ConfigFormat::Json => {
+            let mut de = serde_json::Deserializer::from_str(contents);
+            serde_ignored::deserialize::<_, _, BuildPlan>(&mut de, &mut collect)?;
+        }
+    }
+
+    let schema = serde_json::to_value(plan_schema())?;
+    let unknown_keys = paths
+        .into_iter()
+        .map(|path| UnknownKey {
+            file: file.to_string(),
+            line: find_key_line(contents, &path, format),
+            suggestion: suggest_key(&schema, &path),
+            path,
+        })
+        .collect();
+
+    Ok(unknown_keys)
+}
+
+/// Error if the config file contains any unknown keys
+pub fn validate_config(contents: &str, file: &str, format: ConfigFormat) -> Result<()> {
+    let unknown_keys = find_unknown_keys(contents, file, format)?;
+    if !unknown_keys.is_empty() {
+        bail!(
+            "Invalid Nixpacks config file\n{}",
+            unknown_keys
+                .iter()
+                .map(|key| format!("  {key}"))
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+    }
+
+    Ok(())
+}
+
+fn path_segments(path: &serde_ignored::Path) -> Vec<String> {
+    match path {
+        serde_ignored::Path::Root => Vec::new(),
+        serde_ignored::Path::Seq { parent, index } => {
+            [path_segments(parent), vec![index.to_string()]].concat()
+        }
+        serde_ignored::Path::Map { parent, key } => {
+            [path_segments(parent), vec![key.clone()]].concat()
+        }
+        serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => path_segments(parent),
+    }
+}
+
+/// Suggest the closest known key that could have been meant instead of the last part of `path`
+fn suggest_key(schema: &Value, path: &[String]) -> Option<String> {
+    let (key, parent) = path.split_last()?;
+
+    let mut current = resolve_schema(schema, schema);
+    for segment in parent {
+        let next = current
+            .get("properties")
+            .and_then(|props| props.get(segment))
+            .or_else(|| current.get("additionalProperties"))?;
+        current = resolve_schema(schema, next);
+    }
+
+    current
+        .get("properties")?
+        .as_object()?
+        .keys()
+        .map(|known| (strsim::levenshtein(key, known), known))
+        .filter(|(distance, _)| *distance <= MAX_SUGGESTION_DISTANCE && *distance < key.len())
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, known)| known.clone())
+}
+
+/// Follow `$ref`'s and `anyOf`'s until we reach a schema that describes the value
+fn resolve_schema<'a>(root: &'a Value, schema: &'a Value) -> &'a Value {
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        let name = reference.trim_start_matches("#/definitions/");
+        if let Some(definition) = root.get("definitions").and_then(|defs| defs.get(name)) {
+            return resolve_schema(root, definition);
+        }
+    }
+
+    let variants = schema
+        .get("anyOf")
+        .or_else(|| schema.get("allOf"))
+        .and_then(Value::as_array);
+    if let Some(variants) = variants {
+        if let Some(variant) = variants
+            .iter()
+            .find(|v| v.get("$ref").is_some() || v.get("properties").is_some())
+        {
+            return resolve_schema(root, variant);
+        }
+    }
+
+    schema
+}
+
+/// Best effort search for the (1-based) line that the key at `path` is defined on
+fn find_key_line(contents: &str, path: &[String], format: ConfigFormat) -> Option<usize> {
+    match format {
+        ConfigFormat::Toml => find_toml_key_line(contents, path),
+        ConfigFormat::Json => find_json_key_line(contents, path),
+    }
+}
+
+fn find_toml_key_line(contents: &str, path: &[String]) -> Option<usize> {
+    let mut table: Vec<String> = Vec::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+
+        let full_key = if line.starts_with('[') {
+            table = split_toml_key(line.trim_matches(|c| c == '[' || c == ']'));
+            table.clone()
+        } else if let Some((key, _)) = line.split_once('=') {
+            [table.clone(), split_toml_key(key)].concat()
+        } else {
+            continue;
+        };
+
+        if full_key.starts_with(path) {
+            return Some(i + 1);
+        }
+    }
+
+    None
+}
+
+fn split_toml_key(key: &str) -> Vec<String> {
+    key.split('.')
+        .map(|part| {
+            part.trim()
+                .trim_matches(|c| c == '"' || c == '\'')
+                .to_string()
+        })
+        .collect()
+}
+
+fn find_json_key_line(contents: &str, path: &[String]) -> Option<usize> {
+    let mut offset = 0;
+
+    for segment in path {
+        let needle = format!("\"{segment}\"");
+        let found = contents[offset..]
+            .match_indices(&needle)
+            .map(|(i, _)| offset + i + needle.len())
+            .find(|end| contents[*end..].trim_start().starts_with(':'))?;
+        offset = found;
+    }
+
+    Some(contents[..offset].matches('\n').count() + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema_contains_phase_keys() {
+        let schema = serde_json::to_value(plan_schema()).unwrap();
+        let phase = &schema["definitions"]["Phase"]["properties"];
+
+        assert!(phase.get("cmds").is_some());
+        assert!(phase.get("nixPkgs").is_some());
+        assert!(phase.get("dependsOn").is_some());
+        assert!(schema["definitions"].get("Pkg").is_some());
+    }
+
+    #[test]
+    fn test_finds_unknown_toml_keys() {
+        let contents = r#"buildImg = "debian"
+
+[phases.build]
+dependsOn = ["install"]
+cmd = ["yarn run build"]
+
+[start]
+cmd = "yarn run start"
+"#;
+
+        let unknown = find_unknown_keys(contents, "nixpacks.toml", ConfigFormat::Toml).unwrap();
+        assert_eq!(
+            unknown,
+            vec![
+                UnknownKey {
+                    file: "nixpacks.toml".to_string(),
+                    path: vec!["buildImg".to_string()],
+                    line: Some(1),
+                    suggestion: Some("buildImage".to_string()),
+                },
+                UnknownKey {
+                    file: "nixpacks.toml".to_string(),
+                    path: vec!["phases".to_string(), "build".to_string(), "cmd".to_string()],
+                    line: Some(5),
+                    suggestion: Some("cmds".to_string()),
+                },
+            ]
+        );
+        assert_eq!(
+            unknown[1].to_string(),
+            "nixpacks.toml:5: Unknown key `cmd` in `phases.build`. Did you mean `cmds`?"
+        );
+    }
+
+    #[test]
+    fn test_finds_unknown_json_keys() {
+        let contents = r#"{
+  "phases": {
+    "setup": {
+      "nixPkgs": ["nodejs"]
+    },
+    "build": {
+      "nixPkgs": ["..."],
+      "cacheDirectory": ["node_modules/.cache"]
+    }
+  }
+}"#;
+
+        let unknown = find_unknown_keys(contents, "nixpacks.json", ConfigFormat::Json).unwrap();
+        assert_eq!(unknown.len(), 1);
+        assert_eq!(unknown[0].line, Some(8));
+        assert_eq!(unknown[0].suggestion, Some("cacheDirectories".to_string()));
+    }
+
+    #[test]
+    fn test_aliases_are_not_unknown() {
+        let contents = r#"
+            [phases.setup]
+            nixPackages = ["nodejs"]
+            aptPackages = ["wget"]
+        "#;
+
+        assert!(validate_config(contents, "nixpacks.toml", ConfigFormat::Toml).is_ok());
+    }
+
+    #[test]
+    fn test_no_suggestion_for_unrelated_key() {
+        let contents = r"
+            [phases.setup]
+            completelyDifferent = true
+        ";
+
+        let unknown = find_unknown_keys(contents, "nixpacks.toml", ConfigFormat::Toml).unwrap();
+        assert_eq!(unknown[0].suggestion, None);
+        assert!(validate_config(contents, "nixpacks.toml", ConfigFormat::Toml).is_err());
+    }
+}
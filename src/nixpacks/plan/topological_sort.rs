@@ -63,7 +63,8 @@ where
 
         // Circular dependency
         if no_deps.is_empty() {
-            bail!("Circular dependency detected");
+            let cycle = find_cycle(&lookup, &new_indegree).unwrap_or_default();
+            bail!("Circular dependency detected: {}", cycle.join(" -> "));
         }
 
         indegree = new_indegree;
@@ -91,6 +92,45 @@ where
     Ok(result)
 }
 
+/// Find a path of dependencies that leads back to where it started, among the `remaining` items
+fn find_cycle<T: TopItem>(
+    lookup: &BTreeMap<String, T>,
+    remaining: &BTreeMap<String, usize>,
+) -> Option<Vec<String>> {
+    fn visit<T: TopItem>(
+        name: &str,
+        lookup: &BTreeMap<String, T>,
+        remaining: &BTreeMap<String, usize>,
+        path: &mut Vec<String>,
+        done: &mut BTreeSet<String>,
+    ) -> Option<Vec<String>> {
+        if let Some(start) = path.iter().position(|n| n == name) {
+            return Some([&path[start..], &[name.to_string()]].concat());
+        }
+        if done.contains(name) {
+            return None;
+        }
+
+        path.push(name.to_string());
+        for dep in lookup.get(name)?.get_dependencies() {
+            if remaining.contains_key(dep) {
+                if let Some(cycle) = visit(dep, lookup, remaining, path, done) {
+                    return Some(cycle);
+                }
+            }
+        }
+        path.pop();
+        done.insert(name.to_string());
+
+        None
+    }
+
+    let mut done = BTreeSet::new();
+    remaining
+        .keys()
+        .find_map(|name| visit(name, lookup, remaining, &mut Vec::new(), &mut done))
+}
+
 #[cfg(test)]
 mod tests {
     use crate::nixpacks::plan::topological_sort;
@@ -150,4 +190,19 @@ mod tests {
 
         assert!(topological_sort(items).is_err());
     }
+
+    #[test]
+    fn test_circular_dep_path() {
+        let items = vec![
+            TestItem::new("a", vec![]),
+            TestItem::new("b", vec!["a".to_string(), "d".to_string()]),
+            TestItem::new("c", vec!["b".to_string()]),
+            TestItem::new("d", vec!["c".to_string()]),
+        ];
+
+        assert_eq!(
+            topological_sort(items).unwrap_err().to_string(),
+            "Circular dependency detected: b -> d -> c -> b"
+        );
+    }
 }
@@ -1,3 +1,27 @@
+use crate::nixpacks::environment::EnvironmentVariables;
+
+/// Prefix of a value that removes that value from the values being extended, e.g. `"!chromium"`
+pub const REMOVE_PREFIX: &str = "!";
+
+/// Prefix of a command that removes that command from the commands being extended.
+///
+/// Commands can start with `!` (e.g. `! grep -q foo file`), so they are only removed with `{ remove = ... }`,
+/// which is stored with this prefix because it can not be part of a command.
+pub const REMOVE_CMD_PREFIX: &str = "\0!";
+
+/// The value that a removal marker with the given `prefix` removes
+pub fn get_removal<'a>(value: &'a str, prefix: &str) -> Option<&'a str> {
+    value.strip_prefix(prefix)
+}
+
+/// Removes all the removal markers with the given `prefix` from the `original`
+pub fn remove_removals_from_vec(original: Vec<String>, prefix: &str) -> Vec<String> {
+    original
+        .into_iter()
+        .filter(|x| get_removal(x, prefix).is_none())
+        .collect::<Vec<_>>()
+}
+
 /// Removes all the `"..."`'s or `"@auto"`'s from the `original`
 pub fn remove_autos_from_vec(original: Vec<String>) -> Vec<String> {
     original
@@ -26,9 +50,14 @@ pub fn fill_auto_in_vec(
             .flat_map(|x| {
                 let v = x.clone();
                 if v == *"@auto" || v == *"..." {
-                    let mut fill = vec![v];
-                    fill.append(&mut original.clone());
-                    fill
+                    // Keep a single marker so that chained merges don't fill values in twice
+                    if original.iter().any(|o| o == "@auto" || o == "...") {
+                        original.clone()
+                    } else {
+                        let mut fill = vec![v];
+                        fill.append(&mut original.clone());
+                        fill
+                    }
                 } else {
                     vec![x]
                 }
@@ -41,6 +70,104 @@ pub fn fill_auto_in_vec(
     }
 }
 
+/// Same as `fill_auto_in_vec`, but every removal marker with the given `prefix` in `replacer`
+/// (e.g. `"!value"`) removes its value from the `original`.
+///
+/// A `replacer` that only contains removals extends the `original`.
+pub fn fill_auto_in_vec_with_removals(
+    original: Option<Vec<String>>,
+    replacer: Option<Vec<String>>,
+    prefix: &str,
+) -> Option<Vec<String>> {
+    let mut replacer = match replacer {
+        Some(replacer) => replacer,
+        None => return original,
+    };
+
+    let removals = replacer
+        .iter()
+        .filter_map(|x| get_removal(x, prefix))
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    if removals.is_empty() {
+        return fill_auto_in_vec(original, Some(replacer));
+    }
+
+    if removals.len() == replacer.len() {
+        replacer.insert(0, "...".to_string());
+    }
+
+    // The removals are kept so they also apply to any plans that are merged in later
+    let original = original.map(|original| {
+        original
+            .into_iter()
+            .filter(|x| !removals.contains(x))
+            .collect()
+    });
+
+    fill_auto_in_vec(original, Some(replacer))
+}
+
+/// Replaces every `${NAME}` in `value` with the variable `NAME`.
+///
+/// Escaped references (`$${NAME}`) are kept as they are, so that interpolating a value again does not change it.
+/// They are turned into a literal `${NAME}` by `unescape_variables` once the value is used.
+/// References to undefined variables are left as they are and returned along with the result.
+/// Anything that is not a plain variable name (e.g. `${PORT:-3000}`) is left for the shell.
+pub fn interpolate_variables(
+    value: &str,
+    variables: &EnvironmentVariables,
+) -> (String, Vec<String>) {
+    let mut result = String::with_capacity(value.len());
+    let mut undefined = Vec::new();
+    let mut rest = value;
+
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(escaped) = rest.strip_prefix("$${") {
+            result.push_str("$${");
+            rest = escaped;
+            continue;
+        }
+
+        let reference = rest
+            .strip_prefix("${")
+            .and_then(|inner| inner.split_once('}'))
+            .filter(|(name, _)| is_variable_name(name));
+
+        if let Some((name, after)) = reference {
+            if let Some(value) = variables.get(name) {
+                result.push_str(value);
+            } else {
+                result.push_str(&rest[..name.len() + 3]);
+                undefined.push(name.to_string());
+            }
+            rest = after;
+        } else {
+            result.push('$');
+            rest = &rest[1..];
+        }
+    }
+    result.push_str(rest);
+
+    (result, undefined)
+}
+
+/// Turns the escaped `$${NAME}` references that `interpolate_variables` kept into a literal `${NAME}`
+pub fn unescape_variables(value: &str) -> String {
+    value.replace("$${", "${")
+}
+
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -85,5 +212,87 @@ mod test {
             )
             .unwrap()
         );
+        assert_eq!(
+            vec!["...", "a", "b"],
+            fill_auto_in_vec(Some(vs(vec!["...", "a"])), Some(vs(vec!["...", "b"]))).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_fill_auto_in_vec_with_removals() {
+        assert_eq!(
+            vec!["...", "a", "c", "!b", "z"],
+            fill_auto_in_vec_with_removals(
+                Some(vs(vec!["a", "b", "c"])),
+                Some(vs(vec!["...", "!b", "z"])),
+                REMOVE_PREFIX
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            vec!["...", "a", "!b"],
+            fill_auto_in_vec_with_removals(
+                Some(vs(vec!["a", "b"])),
+                Some(vs(vec!["!b"])),
+                REMOVE_PREFIX
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            vec!["...", "!b"],
+            fill_auto_in_vec_with_removals(None, Some(vs(vec!["!b"])), REMOVE_PREFIX).unwrap()
+        );
+        assert_eq!(
+            vec!["x"],
+            fill_auto_in_vec_with_removals(
+                Some(vs(vec!["a", "b"])),
+                Some(vs(vec!["x"])),
+                REMOVE_PREFIX
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_remove_removals_from_vec() {
+        assert_eq!(
+            vs(vec!["a", "..."]),
+            remove_removals_from_vec(vs(vec!["a", "!b", "..."]), REMOVE_PREFIX)
+        );
+        assert_eq!(
+            vs(vec!["! grep -q foo file"]),
+            remove_removals_from_vec(
+                vs(vec!["! grep -q foo file", "\0!npm run seed"]),
+                REMOVE_CMD_PREFIX
+            )
+        );
+    }
+
+    #[test]
+    fn test_interpolate_variables() {
+        let variables = EnvironmentVariables::from([
+            ("APP_NAME".to_string(), "my-app".to_string()),
+            ("EMPTY".to_string(), String::new()),
+        ]);
+
+        assert_eq!(
+            interpolate_variables("cp -r dist /srv/${APP_NAME}${EMPTY}", &variables),
+            ("cp -r dist /srv/my-app".to_string(), Vec::new())
+        );
+        assert_eq!(
+            interpolate_variables("echo $${APP_NAME} $APP_NAME $$", &variables),
+            ("echo $${APP_NAME} $APP_NAME $$".to_string(), Vec::new())
+        );
+        assert_eq!(
+            unescape_variables("echo $${APP_NAME} $APP_NAME $$"),
+            "echo ${APP_NAME} $APP_NAME $$"
+        );
+        assert_eq!(
+            interpolate_variables("serve -p ${PORT:-3000} ${MISSING}", &variables),
+            (
+                "serve -p ${PORT:-3000} ${MISSING}".to_string(),
+                vec!["MISSING".to_string()]
+            )
+        );
     }
 }
@@ -0,0 +1,260 @@
+use super::{phase::SUPPORTED_INITS, topological_sort::topological_sort, BuildPlan};
+use anyhow::{bail, Result};
+use std::fmt::{self, Display};
+
+/// Ports below this can only be listened on by root
+const MIN_UNPRIVILEGED_PORT: u16 = 1024;
+
+/// Maximum edit distance for an existing phase to be suggested in place of a missing one
+const MAX_SUGGESTION_DISTANCE: usize = 3;
+
+/// A phase that depends on a phase which is not part of the plan
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingDependency {
+    pub phase: String,
+    pub dependency: String,
+    pub suggestion: Option<String>,
+}
+
+impl Display for MissingDependency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Phase `{}` depends on `{}`, which does not exist",
+            self.phase, self.dependency
+        )?;
+
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, ". Did you mean `{suggestion}`?")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl BuildPlan {
+    /// Find all the `dependsOn` entries that reference a phase that is not in the plan
+    pub fn find_missing_dependencies(&self) -> Vec<MissingDependency> {
+        let phases = self.phases.clone().unwrap_or_default();
+
+        let mut missing = Vec::new();
+        for (name, phase) in &phases {
+            for dependency in phase.depends_on.clone().unwrap_or_default() {
+                if !phases.contains_key(&dependency) {
+                    missing.push(MissingDependency {
+                        phase: name.clone(),
+                        suggestion: suggest_phase(
+                            &dependency,
+                            phases.keys().filter(|other| *other != name),
+                        ),
+                        dependency,
+                    });
+                }
+            }
+        }
+
+        missing
+    }
+
+    /// Users other than root can not listen on ports below 1024, such as port 80 of nginx
+    fn find_privileged_port_for_user(&self) -> Option<String> {
+        let start = self.start_phase.as_ref()?;
+        let user = start.user.as_ref().filter(|user| *user != "root")?;
+        let port = self
+            .variables
+            .as_ref()
+            .and_then(|variables| variables.get("PORT"))
+            .and_then(|port| port.parse::<u16>().ok())
+            .or_else(|| start.get_default_port())?;
+
+        (port < MIN_UNPRIVILEGED_PORT).then(|| {
+            format!("The user `{user}` can not listen on port {port}, set `start.ports` or the `PORT` variable to a port of {MIN_UNPRIVILEGED_PORT} or above")
+        })
+    }
+
+    /// Error if a phase depends on a phase that does not exist or if the phases depend on each other in a cycle
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = self
+            .find_missing_dependencies()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+
+        if let Some(init) = self
+            .start_phase
+            .as_ref()
+            .and_then(|start| start.init.as_ref())
+        {
+            if !SUPPORTED_INITS.contains(&init.as_str()) {
+                problems.push(format!(
+                    "Unknown init `{init}`, use one of {}",
+                    SUPPORTED_INITS.join(", ")
+                ));
+            }
+        }
+
+        if let Some(problem) = self.find_privileged_port_for_user() {
+            problems.push(problem);
+        }
+
+        let phases = self.phases.clone().unwrap_or_default();
+        if let Err(e) = topological_sort(phases.into_iter().collect()) {
+            problems.push(e.to_string());
+        }
+
+        if !problems.is_empty() {
+            bail!(
+                "Invalid build plan\n{}",
+                problems
+                    .iter()
+                    .map(|problem| format!("  {problem}"))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Suggest the existing phase that was most likely meant instead of `missing`
+fn suggest_phase<'a>(missing: &str, existing: impl Iterator<Item = &'a String>) -> Option<String> {
+    let existing = existing.collect::<Vec<_>>();
+
+    // A dependency on `install` within a provider's phases is called `node:install`, and vice versa
+    let unprefixed = missing.rsplit(':').next().unwrap_or(missing);
+    if let Some(name) = existing
+        .iter()
+        .find(|name| name.rsplit(':').next() == Some(unprefixed))
+    {
+        return Some((*name).clone());
+    }
+
+    existing
+        .into_iter()
+        .map(|name| (strsim::levenshtein(missing, name), name))
+        .filter(|(distance, _)| *distance <= MAX_SUGGESTION_DISTANCE && *distance < missing.len())
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nixpacks::environment::EnvironmentVariables;
+
+    #[test]
+    fn test_missing_dependency() {
+        let plan = BuildPlan::from_toml(
+            r#"
+            [phases.setup]
+            nixPkgs = ["nodejs"]
+
+            [phases.install]
+            dependsOn = ["setup"]
+
+            [phases.build]
+            dependsOn = ["instal"]
+
+            [phases.test]
+            dependsOn = ["node:build", "lint"]
+            "#,
+        )
+        .unwrap();
+
+        let missing = plan.find_missing_dependencies();
+        assert_eq!(
+            missing.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                "Phase `build` depends on `instal`, which does not exist. Did you mean `install`?",
+                "Phase `test` depends on `node:build`, which does not exist. Did you mean `build`?",
+                "Phase `test` depends on `lint`, which does not exist"
+            ]
+        );
+        assert!(plan.validate().is_err());
+    }
+
+    #[test]
+    fn test_dependency_cycle() {
+        let plan = BuildPlan::from_toml(
+            r#"
+            [phases.setup]
+
+            [phases.install]
+            dependsOn = ["setup", "build"]
+
+            [phases.build]
+            dependsOn = ["install"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            plan.validate().unwrap_err().to_string(),
+            "Invalid build plan\n  Circular dependency detected: build -> install -> build"
+        );
+    }
+
+    #[test]
+    fn test_privileged_port_for_user() {
+        let mut plan = BuildPlan::from_toml(
+            r#"
+            [start]
+            cmd = "nginx -c /assets/nginx.conf"
+            ports = [80]
+            user = "app"
+            "#,
+        )
+        .unwrap();
+        assert!(plan
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("The user `app` can not listen on port 80"));
+
+        // Apps such as nginx of the staticfile and PHP providers listen on `PORT`
+        plan.add_variables(EnvironmentVariables::from([(
+            "PORT".to_string(),
+            "8080".to_string(),
+        )]));
+        assert!(plan.validate().is_ok());
+
+        plan.variables = None;
+        plan.start_phase.as_mut().unwrap().user = Some("root".to_string());
+        assert!(plan.validate().is_ok());
+    }
+
+    #[test]
+    fn test_unknown_init() {
+        let plan = BuildPlan::from_toml(
+            r#"
+            [start]
+            init = "systemd"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            plan.validate().unwrap_err().to_string(),
+            "Invalid build plan\n  Unknown init `systemd`, use one of tini, dumb-init"
+        );
+    }
+
+    #[test]
+    fn test_valid_plan() {
+        let plan = BuildPlan::from_toml(
+            r#"
+            [phases.setup]
+
+            [phases.install]
+            dependsOn = ["setup"]
+
+            [phases.build]
+            dependsOn = ["install", "setup"]
+            "#,
+        )
+        .unwrap();
+
+        assert!(plan.validate().is_ok());
+    }
+}
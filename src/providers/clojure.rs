@@ -44,7 +44,7 @@ impl Provider for ClojureProvider {
         // based on project config, uberjar can be created under ./target/uberjar or ./target, This ensure file will be found on the same place whatevery the project config is
         let move_file_cmd = "if [ -f /app/target/uberjar/*standalone.jar ]; then  mv /app/target/uberjar/*standalone.jar /app/target/*standalone.jar; fi";
         let mut build = Phase::build(Some(format!("{}; {}", build_cmd, move_file_cmd)));
-        build.depends_on_phase("setup");
+        build.depends_on = Some(vec!["setup".to_string()]);
 
         let start = StartPhase::new("bash -c \"java $JAVA_OPTS -jar /app/target/*standalone.jar\"");
 
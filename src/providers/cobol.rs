@@ -52,7 +52,7 @@ impl Provider for CobolProvider {
             file_name,
             app_path.as_os_str().to_str().unwrap()
         )));
-        build.depends_on_phase("setup");
+        build.depends_on = Some(vec!["setup".to_string()]);
 
         let start = StartPhase::new(format!("./{}", file_name));
 
@@ -50,7 +50,7 @@ impl Provider for DenoProvider {
 
         if let Some(build_cmd) = DenoProvider::get_build_cmd(app)? {
             let mut build = Phase::build(Some(build_cmd));
-            build.depends_on_phase("setup");
+            build.depends_on = Some(vec!["setup".to_string()]);
             plan.add_phase(build);
         };
 
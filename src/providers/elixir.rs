@@ -4,13 +4,14 @@ use crate::nixpacks::{
     environment::{Environment, EnvironmentVariables},
     nix::pkg::Pkg,
     plan::{
-        phase::{Phase, StartPhase},
+        phase::{Healthcheck, Phase, StartPhase},
         BuildPlan,
     },
 };
 use anyhow::Result;
 use regex::{Match, Regex};
 const DEFAULT_ELIXIR_PKG_NAME: &str = "elixir";
+const PHOENIX_PORT: u16 = 4000;
 
 pub struct ElixirProvider {}
 
@@ -56,7 +57,9 @@ impl Provider for ElixirProvider {
         plan.add_phase(build_phase);
 
         // Start Phase
-        let start_phase = StartPhase::new("mix phx.server".to_string());
+        let mut start_phase = StartPhase::new("mix phx.server".to_string());
+        start_phase.add_port(PHOENIX_PORT);
+        start_phase.healthcheck = Some(Healthcheck::http("/", PHOENIX_PORT));
         plan.set_start_phase(start_phase);
 
         Ok(Some(plan))
@@ -1,7 +1,7 @@
 use super::Provider;
 use crate::nixpacks::{
     app::App,
-    environment::{Environment, EnvironmentVariables},
+    environment::{Environment, EnvironmentVariables, VariableScope},
     nix::pkg::Pkg,
     plan::{
         phase::{Phase, StartPhase},
@@ -48,7 +48,11 @@ impl Provider for GolangProvider {
             Phase::build(None)
         };
         build.add_cache_directory(GO_BUILD_CACHE_DIR.to_string());
-        build.depends_on_phase("setup");
+        if app.includes_file("go.mod") {
+            build.depends_on_phase("setup");
+        } else {
+            build.depends_on = Some(vec!["setup".to_string()]);
+        }
         plan.add_phase(build);
 
         let has_go_files = app.has_match("**/*.go");
@@ -64,10 +68,10 @@ impl Provider for GolangProvider {
             plan.set_start_phase(start);
         }
 
-        plan.add_variables(EnvironmentVariables::from([(
-            "CGO_ENABLED".to_string(),
-            "0".to_string(),
-        )]));
+        plan.add_scoped_variables(
+            EnvironmentVariables::from([("CGO_ENABLED".to_string(), "0".to_string())]),
+            VariableScope::Build,
+        );
 
         Ok(Some(plan))
     }
@@ -11,6 +11,8 @@ use crate::nixpacks::{
 use anyhow::Result;
 use regex::{Match, Regex};
 
+const JAVA_PORT: u16 = 8080;
+
 pub struct JavaProvider {}
 
 impl Provider for JavaProvider {
@@ -50,7 +52,13 @@ impl Provider for JavaProvider {
             build.add_cache_directory(".m2/repository");
             build
         };
-        let start = StartPhase::new(self.get_start_cmd(app)?);
+        let start_cmd = self.get_start_cmd(app)?;
+        let mut start = StartPhase::new(start_cmd.clone());
+
+        // Spring Boot and WildFly Swarm apps are told to listen on $PORT
+        if start_cmd.contains("$PORT") {
+            start.add_port(JAVA_PORT);
+        }
         build.depends_on = Some(vec!["setup".to_string()]);
 
         let plan = BuildPlan::new(&vec![setup, build], Some(start));
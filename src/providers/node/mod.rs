@@ -2,7 +2,7 @@ use self::{nx::Nx, turborepo::Turborepo};
 use super::Provider;
 use crate::nixpacks::{
     app::App,
-    environment::{Environment, EnvironmentVariables},
+    environment::{Environment, EnvironmentVariables, VariableScope},
     nix::pkg::Pkg,
     plan::{
         phase::{Phase, StartPhase},
@@ -127,6 +127,10 @@ impl Provider for NodeProvider {
 
         let mut plan = BuildPlan::new(&vec![setup, install, build], start);
         plan.add_variables(NodeProvider::get_node_environment_variables());
+        plan.add_scoped_variables(
+            NodeProvider::get_node_build_variables(),
+            VariableScope::Build,
+        );
 
         Ok(Some(plan))
     }
@@ -134,8 +138,12 @@ impl Provider for NodeProvider {
 
 impl NodeProvider {
     pub fn get_node_environment_variables() -> EnvironmentVariables {
+        EnvironmentVariables::from([("NODE_ENV".to_string(), "production".to_string())])
+    }
+
+    /// Variables that are only needed to install and build the app
+    pub fn get_node_build_variables() -> EnvironmentVariables {
         EnvironmentVariables::from([
-            ("NODE_ENV".to_string(), "production".to_string()),
             ("NPM_CONFIG_PRODUCTION".to_string(), "false".to_string()),
             ("CI".to_string(), "true".to_string()),
         ])
@@ -16,6 +16,8 @@ use super::{node::NodeProvider, Provider};
 use anyhow::Result;
 
 const DEFAULT_PHP_VERSION: &str = "8.1";
+const NGINX_PORT: u16 = 80;
+const NGINX_WRITABLE_DIRS: &[&str] = &["/assets", "/var/log/nginx", "/var/cache/nginx"];
 
 pub struct PhpProvider;
 
@@ -105,12 +107,18 @@ impl PhpProvider {
     }
 
     fn get_start(app: &App) -> StartPhase {
-        StartPhase::new(format!(
-            "([ -e /app/storage ] && chmod -R ugo+w /app/storage); perl {} {} /nginx.conf && echo \"Server starting on port $PORT\" && (php-fpm -y {} & nginx -c /nginx.conf)",
+        let mut start = StartPhase::new(format!(
+            "([ -e /app/storage ] && chmod -R ugo+w /app/storage); perl {} {} {nginx_conf} && echo \"Server starting on port $PORT\" && (php-fpm -y {} & nginx -c {nginx_conf})",
             app.asset_path("transform-config.pl"),
             app.asset_path("nginx.template.conf"),
             app.asset_path("php-fpm.conf"),
-        ))
+            nginx_conf = app.asset_path("nginx.conf"),
+        ));
+        start.add_port(NGINX_PORT);
+        for dir in NGINX_WRITABLE_DIRS {
+            start.add_writable_directory(*dir);
+        }
+        start
     }
 
     fn static_assets() -> StaticAssets {
@@ -123,7 +131,6 @@ impl PhpProvider {
 
     fn environment_variables(app: &App) -> EnvironmentVariables {
         let mut vars = EnvironmentVariables::new();
-        vars.insert("PORT".to_string(), "80".to_string());
         if app.includes_file("artisan") {
             vars.insert("IS_LARAVEL".to_string(), "yes".to_string());
         }
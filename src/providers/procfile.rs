@@ -5,7 +5,7 @@ use crate::nixpacks::{
     app::App,
     environment::Environment,
     plan::{
-        phase::{Phase, StartPhase},
+        phase::{Phase, Processes, StartPhase},
         BuildPlan,
     },
 };
@@ -32,8 +32,15 @@ impl Provider for ProcfileProvider {
             plan.add_phase(release);
         };
 
-        if let Some(start_cmd) = ProcfileProvider::get_start_cmd(app)? {
-            let start_phase = StartPhase::new(start_cmd);
+        let processes = ProcfileProvider::get_processes(app)?;
+        if let Some(start_cmd) = ProcfileProvider::get_start_cmd(&processes) {
+            let mut start_phase = StartPhase::new(start_cmd);
+
+            // Only name the processes when there is more than one to choose from
+            if processes.len() > 1 {
+                start_phase.processes = Some(processes);
+            }
+
             plan.set_start_phase(start_phase);
         }
 
@@ -42,25 +49,24 @@ impl Provider for ProcfileProvider {
 }
 
 impl ProcfileProvider {
-    fn get_start_cmd(app: &App) -> Result<Option<String>> {
+    /// All the processes in the Procfile except for `release`
+    fn get_processes(app: &App) -> Result<Processes> {
         if app.includes_file("Procfile") {
-            let mut procfile: HashMap<String, String> =
-                app.read_yaml("Procfile").context("Reading Procfile")?;
+            let mut procfile: Processes = app.read_yaml("Procfile").context("Reading Procfile")?;
             procfile.remove("release");
-
-            if procfile.is_empty() {
-                Ok(None)
-            } else if let Some(cmd) = procfile.get("web") {
-                Ok(Some(cmd.to_string()))
-            } else {
-                let process = procfile.values().collect::<Vec<_>>()[0].to_string();
-                Ok(Some(process))
-            }
+            Ok(procfile)
         } else {
-            Ok(None)
+            Ok(Processes::default())
         }
     }
 
+    fn get_start_cmd(processes: &Processes) -> Option<String> {
+        processes
+            .get("web")
+            .or_else(|| processes.values().next())
+            .cloned()
+    }
+
     fn get_release_cmd(app: &App) -> Result<Option<String>> {
         if app.includes_file("Procfile") {
             let procfile: HashMap<String, String> =
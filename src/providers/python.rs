@@ -2,9 +2,9 @@ use crate::{
     chain,
     nixpacks::{
         app::App,
-        environment::{Environment, EnvironmentVariables},
+        environment::{Environment, EnvironmentVariables, VariableScope},
         plan::{
-            phase::{Phase, StartPhase},
+            phase::{Healthcheck, Phase, StartPhase},
             BuildPlan,
         },
     },
@@ -21,6 +21,7 @@ use super::{Provider, ProviderMetadata};
 const DEFAULT_PYTHON_PKG_NAME: &str = "python38";
 const POETRY_VERSION: &str = "1.1.13";
 const PIP_CACHE_DIR: &str = "/root/.cache/pip";
+const GUNICORN_PORT: u16 = 8000;
 
 pub struct PythonProvider {}
 
@@ -67,10 +68,13 @@ impl Provider for PythonProvider {
         )]));
 
         if app.includes_file("poetry.lock") {
-            plan.add_variables(EnvironmentVariables::from([(
-                "NIXPACKS_POETRY_VERSION".to_string(),
-                POETRY_VERSION.to_string(),
-            )]));
+            plan.add_scoped_variables(
+                EnvironmentVariables::from([(
+                    "NIXPACKS_POETRY_VERSION".to_string(),
+                    POETRY_VERSION.to_string(),
+                )]),
+                VariableScope::Build,
+            );
         }
 
         Ok(Some(plan))
@@ -181,10 +185,12 @@ impl PythonProvider {
         if PythonProvider::is_django(app, env)? {
             let app_name = PythonProvider::get_django_app_name(app, env)?;
 
-            return Ok(Some(StartPhase::new(format!(
-                "python manage.py migrate && gunicorn {}",
-                app_name
-            ))));
+            let mut start_phase =
+                StartPhase::new(format!("python manage.py migrate && gunicorn {app_name}"));
+            start_phase.add_port(GUNICORN_PORT);
+            start_phase.healthcheck = Some(Healthcheck::http("/", GUNICORN_PORT));
+
+            return Ok(Some(start_phase));
         }
 
         if app.includes_file("pyproject.toml") {
@@ -4,7 +4,7 @@ use crate::nixpacks::{
     environment::{Environment, EnvironmentVariables},
     nix::pkg::Pkg,
     plan::{
-        phase::{Phase, StartPhase},
+        phase::{Healthcheck, Phase, StartPhase},
         BuildPlan,
     },
 };
@@ -14,6 +14,8 @@ use regex::Regex;
 pub struct RubyProvider {}
 
 const BUNDLE_CACHE_DIR: &str = "/root/.bundle/cache";
+const DEFAULT_PORT: u16 = 3000;
+const RAILS_WRITABLE_DIRS: &[&str] = &["tmp", "log", "storage"];
 
 impl Provider for RubyProvider {
     fn name(&self) -> &str {
@@ -123,7 +125,17 @@ impl RubyProvider {
 
     fn get_start(&self, app: &App) -> Result<Option<StartPhase>> {
         if let Some(start_cmd) = self.get_start_command(app) {
-            Ok(Some(StartPhase::new(start_cmd)))
+            let mut start_phase = StartPhase::new(start_cmd.clone());
+            if start_cmd.contains("PORT") {
+                start_phase.add_port(DEFAULT_PORT);
+            }
+            if self.is_rails_app(app) {
+                start_phase.healthcheck = self.get_rails_healthcheck(app);
+                for dir in RAILS_WRITABLE_DIRS {
+                    start_phase.add_writable_directory(*dir);
+                }
+            }
+            Ok(Some(start_phase))
         } else {
             Ok(None)
         }
@@ -206,6 +218,14 @@ impl RubyProvider {
         }
     }
 
+    /// Rails 7.1 and later add a `/up` route that only checks that the app booted
+    fn get_rails_healthcheck(&self, app: &App) -> Option<Healthcheck> {
+        app.read_file("config/routes.rb")
+            .unwrap_or_default()
+            .contains("rails/health")
+            .then(|| Healthcheck::http("/up", DEFAULT_PORT))
+    }
+
     fn is_rails_app(&self, app: &App) -> bool {
         app.includes_file("config/application.rb")
             && app
@@ -272,4 +292,25 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_rails_healthcheck_needs_health_route() -> Result<()> {
+        assert_eq!(
+            RubyProvider {}.get_rails_healthcheck(&App::new("./examples/ruby-rails-postgres")?),
+            None
+        );
+
+        let dir = tempdir::TempDir::new("rails-health-route")?;
+        std::fs::create_dir(dir.path().join("config"))?;
+        std::fs::write(
+            dir.path().join("config/routes.rb"),
+            "Rails.application.routes.draw do\n  get \"up\" => \"rails/health#show\"\nend\n",
+        )?;
+        assert_eq!(
+            RubyProvider {}.get_rails_healthcheck(&App::new(dir.path().to_str().unwrap())?),
+            Some(Healthcheck::http("/up", DEFAULT_PORT))
+        );
+
+        Ok(())
+    }
 }
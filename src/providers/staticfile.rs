@@ -14,6 +14,9 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt::Write as _;
 
+const NGINX_PORT: u16 = 80;
+const NGINX_WRITABLE_DIRS: &[&str] = &["/assets", "/var/log/nginx", "/var/cache/nginx"];
+
 #[derive(Serialize, Deserialize, Default, Debug)]
 pub struct Staticfile {
     pub root: Option<String>,
@@ -41,13 +44,16 @@ impl Provider for StaticfileProvider {
         let mut setup = Phase::setup(Some(vec![Pkg::new("nginx")]));
         setup.add_cmd("mkdir /etc/nginx/ /var/log/nginx/ /var/cache/nginx/");
 
-        // shell command to edit 0.0.0.0:80 to $PORT
-        let shell_cmd = "[[ -z \"${PORT}\" ]] && echo \"Environment variable PORT not found. Using PORT 80\" || sed -i \"s/0.0.0.0:80/$PORT/g\"";
-        let start = StartPhase::new(format!(
+        // shell command to edit 0.0.0.0:80 to $PORT, unless PORT is set to nothing
+        let shell_cmd = format!("[[ -z \"${{PORT}}\" ]] && echo \"Environment variable PORT not found. Using PORT {NGINX_PORT}\" || sed -i \"s/0.0.0.0:80/$PORT/g\"");
+        let mut start = StartPhase::new(format!(
             "{shell_cmd} {conf_location} && nginx -c {conf_location}",
-            shell_cmd = shell_cmd,
             conf_location = app.asset_path("nginx.conf"),
         ));
+        start.add_port(NGINX_PORT);
+        for dir in NGINX_WRITABLE_DIRS {
+            start.add_writable_directory(*dir);
+        }
 
         let static_assets = StaticfileProvider::get_static_assets(app, env)?;
 
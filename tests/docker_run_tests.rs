@@ -2,8 +2,9 @@ use anyhow::Context;
 use nixpacks::{
     create_docker_image,
     nixpacks::{
-        builder::docker::DockerBuilderOptions, environment::EnvironmentVariables,
-        plan::generator::GeneratePlanOptions,
+        builder::docker::DockerBuilderOptions,
+        environment::EnvironmentVariables,
+        plan::{generator::GeneratePlanOptions, BuildPlan},
     },
 };
 use std::io::{BufRead, BufReader};
@@ -695,6 +696,29 @@ fn test_go() {
     assert!(output.contains("Hello from Go"));
 }
 
+#[test]
+fn test_go_with_init_in_run_image() {
+    let name = Uuid::new_v4().to_string();
+    create_docker_image(
+        "./examples/go",
+        Vec::new(),
+        &GeneratePlanOptions {
+            plan: Some(BuildPlan::from_toml("[start]\ninit = \"tini\"").unwrap()),
+            ..Default::default()
+        },
+        &DockerBuilderOptions {
+            name: Some(name.clone()),
+            quiet: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    // The init is copied into the slim run image, which does not have the Nix store
+    let output = run_image(&name, None);
+    assert!(output.contains("Hello from Go"));
+}
+
 #[test]
 fn test_go_custom_version() {
     let name = simple_build("./examples/go-custom-version");
@@ -924,3 +948,118 @@ fn test_multiple_providers() {
     assert!(output.contains("go"));
     assert!(output.contains("deno"));
 }
+
+/// Builds the app with the nixpacks binary on the buildx builder, returning everything it printed
+fn build_with_buildx_builder(builder: &str, name: &str, args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_nixpacks"))
+        .args(["build", "./examples/node", "--name", name, "--verbose"])
+        .args(args)
+        .env("BUILDX_BUILDER", builder)
+        .output()
+        .unwrap();
+    let printed = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(output.status.success(), "{}", printed);
+    printed
+}
+
+#[test]
+fn test_local_cache_is_used_by_next_build() {
+    // Only buildx builders that do not use the docker driver can export a local cache
+    let builder = format!("nixpacks-cache-{}", Uuid::new_v4());
+    Command::new("docker")
+        .args([
+            "buildx",
+            "create",
+            "--driver",
+            "docker-container",
+            "--name",
+            &builder,
+        ])
+        .status()
+        .unwrap();
+
+    let tmp = tempdir::TempDir::new("nixpacks-cache").unwrap();
+    let cache = tmp.path().to_str().unwrap();
+    let name = Uuid::new_v4().to_string();
+
+    build_with_buildx_builder(
+        &builder,
+        &name,
+        &["--cache-to", &format!("type=local,dest={},mode=max", cache)],
+    );
+    assert!(tmp.path().join("index.json").is_file());
+
+    // Drop the cache of the builder itself, so the next build can only use the local cache
+    Command::new("docker")
+        .args(["buildx", "prune", "--all", "--force", "--builder", &builder])
+        .status()
+        .unwrap();
+    let output = build_with_buildx_builder(
+        &builder,
+        &name,
+        &["--cache-from", &format!("type=local,src={}", cache)],
+    );
+
+    Command::new("docker")
+        .args(["buildx", "rm", &builder])
+        .status()
+        .unwrap();
+    assert!(output.contains("CACHED"));
+    assert!(run_image(&name, None).contains("Hello from Node"));
+}
+
+#[test]
+fn test_push_to_local_registry() {
+    let port = portpicker::pick_unused_port().unwrap();
+    let registry = format!("nixpacks-registry-{}", port);
+    Command::new("docker")
+        .args(["run", "-d", "--rm", "--name", &registry])
+        .arg("-p")
+        .arg(format!("{}:5000", port))
+        .arg("registry:2")
+        .status()
+        .unwrap();
+    std::thread::sleep(Duration::from_secs(2));
+
+    let tmp = tempdir::TempDir::new("nixpacks-push").unwrap();
+    let digest_file = tmp.path().join("digests");
+    let metadata_file = tmp.path().join("metadata.json");
+
+    let name = format!("localhost:{}/node", port);
+    let tag = format!("{}:v1", name);
+    let result = create_docker_image(
+        "./examples/node",
+        Vec::new(),
+        &GeneratePlanOptions::default(),
+        &DockerBuilderOptions {
+            name: Some(name.clone()),
+            tags: vec![tag.clone()],
+            quiet: true,
+            push: true,
+            digest_file: Some(digest_file.to_str().unwrap().to_string()),
+            metadata_file: Some(metadata_file.to_str().unwrap().to_string()),
+            ..Default::default()
+        },
+    );
+    Command::new("docker")
+        .args(["stop", &registry])
+        .status()
+        .unwrap();
+    result.unwrap();
+
+    let digests = std::fs::read_to_string(digest_file).unwrap();
+    let lines = digests.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with(&format!("{}@sha256:", name)));
+    assert!(lines[1].starts_with(&format!("{}@sha256:", tag)));
+
+    let metadata: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(metadata_file).unwrap()).unwrap();
+    assert!(metadata["imageId"].as_str().unwrap().starts_with("sha256:"));
+    assert_eq!(metadata["providers"], serde_json::json!(["node"]));
+    assert_eq!(metadata["digests"].as_object().unwrap().len(), 2);
+}
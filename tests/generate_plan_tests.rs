@@ -1,4 +1,6 @@
-use nixpacks::{generate_build_plan, nixpacks::plan::generator::GeneratePlanOptions};
+use nixpacks::{
+    generate_build_plan, lock_build_plan, nixpacks::plan::generator::GeneratePlanOptions,
+};
 use std::env::consts::ARCH;
 
 test_helper::generate_plan_tests!();
@@ -136,3 +138,111 @@ fn test_node_turborepo_custom_app() {
     .unwrap();
     assert!(plan.start_phase.unwrap().cmd.unwrap().contains("docs"));
 }
+
+#[test]
+fn test_config_extends_cycle() {
+    let dir = tempdir::TempDir::new("config-extends-cycle").unwrap();
+    std::fs::create_dir(dir.path().join("shared")).unwrap();
+    std::fs::write(
+        dir.path().join("nixpacks.toml"),
+        "extends = [\"shared/base.toml\"]",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.path().join("shared/base.toml"),
+        "extends = [\"../nixpacks.toml\"]",
+    )
+    .unwrap();
+
+    let err = generate_build_plan(
+        dir.path().to_str().unwrap(),
+        Vec::new(),
+        &GeneratePlanOptions::default(),
+    )
+    .unwrap_err();
+
+    assert!(format!("{err:?}").contains(
+        "Nixpacks config files extend each other in a cycle: nixpacks.toml -> shared/base.toml -> nixpacks.toml"
+    ));
+}
+
+#[test]
+fn test_config_profile() {
+    let plan = generate_build_plan(
+        "./examples/config-profiles",
+        Vec::new(),
+        &GeneratePlanOptions {
+            profile: Some("production".to_string()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    assert_eq!(plan.profile, Some("production".to_string()));
+    assert_eq!(
+        plan.variables.unwrap().get("LOG_LEVEL"),
+        Some(&"info".to_string())
+    );
+    assert_eq!(
+        plan.start_phase.unwrap().cmd,
+        Some("NODE_ENV=production node index.js".to_string())
+    );
+}
+
+#[test]
+fn test_node_lockfile() {
+    let plan = simple_gen_plan("./examples/node-lockfile");
+    let setup = plan.get_phase("setup").unwrap();
+
+    assert_eq!(
+        setup.nixpkgs_archive,
+        Some("293a28df6d7ff3dec1e61e37cc4ee6e6c0fb0847".to_string())
+    );
+    assert_eq!(
+        setup.nix_pkgs,
+        Some(vec!["nodejs-14_x".to_string(), "npm-8_x".to_string()])
+    );
+
+    let unlocked = generate_build_plan(
+        "./examples/node-lockfile",
+        Vec::new(),
+        &GeneratePlanOptions {
+            update_lock: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        unlocked.get_phase("setup").unwrap().nix_pkgs,
+        Some(vec!["nodejs-16_x".to_string(), "npm-8_x".to_string()])
+    );
+}
+
+#[test]
+fn test_lockfile_with_init() {
+    let dir = tempdir::TempDir::new("lockfile-with-init").unwrap();
+    std::fs::write(
+        dir.path().join("package.json"),
+        r#"{ "name": "app", "scripts": { "start": "node index.js" } }"#,
+    )
+    .unwrap();
+    std::fs::write(dir.path().join("index.js"), "").unwrap();
+    std::fs::write(
+        dir.path().join("nixpacks.toml"),
+        "[start]\ninit = \"tini\"\n",
+    )
+    .unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    lock_build_plan(path, Vec::new(), &GeneratePlanOptions::default(), false).unwrap();
+    let plan = generate_build_plan(path, Vec::new(), &GeneratePlanOptions::default()).unwrap();
+
+    let nix_pkgs = plan.get_phase("setup").unwrap().nix_pkgs.clone().unwrap();
+    assert_eq!(
+        nix_pkgs
+            .iter()
+            .filter(|pkg| *pkg == "pkgsStatic.tini")
+            .count(),
+        1
+    );
+}